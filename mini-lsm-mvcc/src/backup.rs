@@ -0,0 +1,319 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::lsm_storage::MiniLsm;
+
+/// A file of a backup, with its path relative to the backup directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub path: String,
+    pub size: u64,
+    pub checksum: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub backup_id: u32,
+    /// Seconds since the Unix epoch when the backup was created.
+    pub timestamp: u64,
+    /// The latest commit timestamp in the backup.
+    pub commit_ts: u64,
+    pub files: Vec<BackupFile>,
+}
+
+/// Incremental backups of a database. SSTs are never modified once written, so an SST is only copied into the
+/// `shared` directory by the first backup containing it, and later backups refer to the same copy. The WAL and the
+/// manifest of each backup are kept in `private/<backup_id>`, and its metadata in `meta/<backup_id>`, which is
+/// written last so that a backup only exists once complete.
+///
+/// All backups in a directory must be taken from the same database, as SSTs are identified by their ids.
+pub struct BackupEngine {
+    dir: PathBuf,
+    /// Backups are created and deleted one at a time.
+    lock: Mutex<()>,
+}
+
+fn file_name(path: &Path) -> Result<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("invalid file name {:?}", path))
+}
+
+/// Read a file, returning its size and checksum. The content is written to `dest` if given.
+fn read_file(path: &Path, mut dest: Option<&mut File>) -> Result<(u64, u32)> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; 64 << 10];
+    let mut size = 0;
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+        if let Some(dest) = dest.as_mut() {
+            dest.write_all(&buf[..len])?;
+        }
+        size += len as u64;
+    }
+    if let Some(dest) = dest {
+        dest.sync_all()?;
+    }
+    Ok((size, hasher.finalize()))
+}
+
+fn sync_dir(path: &Path) -> Result<()> {
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+impl BackupEngine {
+    /// Open the backups in `dir`, creating it if missing. Files left behind by an interrupted backup are removed.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(dir.join("shared"))?;
+        std::fs::create_dir_all(dir.join("private"))?;
+        std::fs::create_dir_all(dir.join("meta"))?;
+        let engine = Self {
+            dir,
+            lock: Mutex::new(()),
+        };
+        if engine.tmp_dir().exists() {
+            std::fs::remove_dir_all(engine.tmp_dir())?;
+        }
+        engine.remove_unreferenced_files()?;
+        Ok(engine)
+    }
+
+    fn tmp_dir(&self) -> PathBuf {
+        self.dir.join("tmp")
+    }
+
+    fn meta_path(&self, backup_id: u32) -> PathBuf {
+        self.dir.join("meta").join(backup_id.to_string())
+    }
+
+    fn private_dir(&self, backup_id: u32) -> PathBuf {
+        self.dir.join("private").join(backup_id.to_string())
+    }
+
+    /// All complete backups, from the oldest to the latest.
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        let mut backups = Vec::new();
+        for entry in std::fs::read_dir(self.dir.join("meta"))? {
+            let path = entry?.path();
+            // a metadata file being written
+            if file_name(&path)?.parse::<u32>().is_err() {
+                continue;
+            }
+            let info: BackupInfo = serde_json::from_slice(&std::fs::read(&path)?)
+                .with_context(|| format!("invalid backup metadata {:?}", path))?;
+            backups.push(info);
+        }
+        backups.sort_by_key(|info| info.backup_id);
+        Ok(backups)
+    }
+
+    pub fn backup_info(&self, backup_id: u32) -> Result<BackupInfo> {
+        let path = self.meta_path(backup_id);
+        if !path.exists() {
+            bail!("backup {} not found", backup_id);
+        }
+        serde_json::from_slice(&std::fs::read(&path)?)
+            .with_context(|| format!("invalid backup metadata {:?}", path))
+    }
+
+    /// Back up a checkpoint of `db`, copying only the SSTs not in an earlier backup.
+    pub fn create_backup(&self, db: &MiniLsm) -> Result<BackupInfo> {
+        let _lock = self.lock.lock();
+        let backups = self.list_backups()?;
+        let backup_id = backups.last().map_or(1, |info| info.backup_id + 1);
+        let result = self.create_backup_inner(db, backup_id, &backups);
+        if result.is_err() {
+            // remove the checkpoint and the files only referenced by this backup, so that the next one starts clean
+            let _ = std::fs::remove_dir_all(self.tmp_dir());
+            let _ = self.remove_unreferenced_files();
+        }
+        result
+    }
+
+    fn create_backup_inner(
+        &self,
+        db: &MiniLsm,
+        backup_id: u32,
+        backups: &[BackupInfo],
+    ) -> Result<BackupInfo> {
+        let shared_files = backups
+            .iter()
+            .flat_map(|info| &info.files)
+            .map(|file| (file.path.as_str(), (file.size, file.checksum)))
+            .collect::<HashMap<_, _>>();
+        let tmp_dir = self.tmp_dir();
+        let commit_ts = db.inner.checkpoint(&tmp_dir)?;
+
+        let private_dir = self.private_dir(backup_id);
+        std::fs::create_dir_all(&private_dir)?;
+        let mut files = Vec::new();
+        let mut copied = 0;
+        for entry in std::fs::read_dir(&tmp_dir)? {
+            let path = entry?.path();
+            let name = file_name(&path)?;
+            if !name.ends_with(".sst") {
+                let (size, checksum) = read_file(&path, None)?;
+                std::fs::rename(&path, private_dir.join(name))?;
+                files.push(BackupFile {
+                    path: format!("private/{}/{}", backup_id, name),
+                    size,
+                    checksum,
+                });
+                continue;
+            }
+            let shared_path = self.dir.join("shared").join(name);
+            let backup_path = format!("shared/{}", name);
+            let (size, checksum) = if let Some(backed_up) = shared_files.get(backup_path.as_str()) {
+                let (size, checksum) = read_file(&path, None)?;
+                if *backed_up != (size, checksum) {
+                    bail!(
+                        "{} differs from the backed up file, the backups are of another database",
+                        name
+                    );
+                }
+                (size, checksum)
+            } else {
+                // the checkpoint links the SSTs of the database, so the backup gets its own copy
+                let tmp_path = self.dir.join("shared").join(format!("{}.tmp", name));
+                let result = read_file(&path, Some(&mut File::create(&tmp_path)?))?;
+                std::fs::rename(&tmp_path, &shared_path)?;
+                copied += 1;
+                result
+            };
+            files.push(BackupFile {
+                path: backup_path,
+                size,
+                checksum,
+            });
+        }
+        std::fs::remove_dir_all(&tmp_dir)?;
+        sync_dir(&self.dir.join("shared"))?;
+        sync_dir(&private_dir)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let info = BackupInfo {
+            backup_id,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            commit_ts,
+            files,
+        };
+        let meta_path = self.meta_path(backup_id);
+        let tmp_path = meta_path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(&info)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &meta_path)?;
+        sync_dir(&self.dir.join("meta"))?;
+        println!(
+            "backup {} created with {} files, {} SSTs copied",
+            backup_id,
+            info.files.len(),
+            copied
+        );
+        Ok(info)
+    }
+
+    /// Delete a backup, and the SSTs no other backup refers to.
+    pub fn delete_backup(&self, backup_id: u32) -> Result<()> {
+        let _lock = self.lock.lock();
+        let meta_path = self.meta_path(backup_id);
+        if !meta_path.exists() {
+            bail!("backup {} not found", backup_id);
+        }
+        std::fs::remove_file(&meta_path)?;
+        sync_dir(&self.dir.join("meta"))?;
+        self.remove_unreferenced_files()
+    }
+
+    /// Delete all backups but the latest `num_backups_to_keep`.
+    pub fn purge_old_backups(&self, num_backups_to_keep: usize) -> Result<()> {
+        let backups = self.list_backups()?;
+        let num_to_delete = backups.len().saturating_sub(num_backups_to_keep);
+        for info in &backups[..num_to_delete] {
+            self.delete_backup(info.backup_id)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the files of deleted or incomplete backups.
+    fn remove_unreferenced_files(&self) -> Result<()> {
+        let backups = self.list_backups()?;
+        let live_ids = backups
+            .iter()
+            .map(|info| info.backup_id.to_string())
+            .collect::<HashSet<_>>();
+        let live_files = backups
+            .iter()
+            .flat_map(|info| info.files.iter().map(|file| file.path.clone()))
+            .collect::<HashSet<_>>();
+        for entry in std::fs::read_dir(self.dir.join("private"))? {
+            let path = entry?.path();
+            if !live_ids.contains(file_name(&path)?) {
+                std::fs::remove_dir_all(&path)?;
+            }
+        }
+        for entry in std::fs::read_dir(self.dir.join("shared"))? {
+            let path = entry?.path();
+            if !live_files.contains(&format!("shared/{}", file_name(&path)?)) {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        for entry in std::fs::read_dir(self.dir.join("meta"))? {
+            let path = entry?.path();
+            if !live_ids.contains(file_name(&path)?) {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the size and checksum of every file of a backup.
+    pub fn verify(&self, backup_id: u32) -> Result<()> {
+        let info = self.backup_info(backup_id)?;
+        for file in &info.files {
+            let path = self.dir.join(&file.path);
+            if !path.exists() {
+                bail!("{} is missing in backup {}", file.path, backup_id);
+            }
+            let (size, checksum) = read_file(&path, None)?;
+            if size != file.size || checksum != file.checksum {
+                bail!("{} is corrupted in backup {}", file.path, backup_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore a backup into `db_dir`, which must be empty or missing, checking every file copied.
+    pub fn restore(&self, backup_id: u32, db_dir: impl AsRef<Path>) -> Result<()> {
+        let db_dir = db_dir.as_ref();
+        let info = self.backup_info(backup_id)?;
+        if db_dir.exists() && std::fs::read_dir(db_dir)?.next().is_some() {
+            bail!("restore directory {:?} is not empty", db_dir);
+        }
+        std::fs::create_dir_all(db_dir)?;
+        for file in &info.files {
+            let path = self.dir.join(&file.path);
+            let mut dest = File::create(db_dir.join(file_name(&path)?))?;
+            let (size, checksum) = read_file(&path, Some(&mut dest))?;
+            if size != file.size || checksum != file.checksum {
+                bail!("{} is corrupted in backup {}", file.path, backup_id);
+            }
+        }
+        sync_dir(db_dir)?;
+        Ok(())
+    }
+}
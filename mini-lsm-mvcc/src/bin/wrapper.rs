@@ -1,5 +1,54 @@
 pub mod mini_lsm_wrapper {
     pub use mini_lsm_mvcc::*;
+
+    /// The CLI is shared with the other engines, so it only sets the options they have in common. The options of
+    /// this engine beyond those keep their defaults.
+    #[allow(dead_code)]
+    pub mod lsm_storage {
+        use std::{ops::Deref, path::Path, sync::Arc};
+
+        use anyhow::Result;
+        use mini_lsm_mvcc::compact::CompactionOptions;
+        use mini_lsm_mvcc::lsm_storage as engine;
+
+        // e.g. `LsmStorageState` for the compaction simulator
+        #[allow(unused_imports)]
+        pub use mini_lsm_mvcc::lsm_storage::*;
+
+        pub struct LsmStorageOptions {
+            pub block_size: usize,
+            pub target_sst_size: usize,
+            pub num_memtable_limit: usize,
+            pub compaction_options: CompactionOptions,
+            pub enable_wal: bool,
+            pub serializable: bool,
+        }
+
+        pub struct MiniLsm(Arc<engine::MiniLsm>);
+
+        impl MiniLsm {
+            pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
+                let options = engine::LsmStorageOptions {
+                    block_size: options.block_size,
+                    target_sst_size: options.target_sst_size,
+                    num_memtable_limit: options.num_memtable_limit,
+                    compaction_options: options.compaction_options,
+                    enable_wal: options.enable_wal,
+                    serializable: options.serializable,
+                    ..Default::default()
+                };
+                Ok(Arc::new(Self(engine::MiniLsm::open(path, options)?)))
+            }
+        }
+
+        impl Deref for MiniLsm {
+            type Target = engine::MiniLsm;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+    }
 }
 
 #[allow(dead_code)]
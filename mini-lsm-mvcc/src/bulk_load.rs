@@ -0,0 +1,197 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use parking_lot::MutexGuard;
+
+use crate::gc::PendingOutputs;
+use crate::key::KeySlice;
+use crate::lsm_storage::LsmStorageInner;
+use crate::manifest::VersionEdit;
+use crate::mvcc::conflict::ConflictSet;
+use crate::table::{SsTable, SsTableBuilder};
+
+/// A session loading sorted data directly into SSTs of the bottom level, without going through the memtable and
+/// the WAL. All keys are committed at one timestamp, and become visible at once when the session finishes. Keys
+/// must be added in increasing order, and must not overlap the SSTs already in the bottom level.
+///
+/// Background compactions are paused, and other writes wait for the session to finish, so it must not write to the
+/// database itself. Dropping the session without finishing it removes the SSTs written so far.
+pub struct BulkLoad<'a> {
+    inner: &'a LsmStorageInner,
+    ts: u64,
+    builder: Option<SsTableBuilder>,
+    tables: Vec<Arc<SsTable>>,
+    last_key: Option<Vec<u8>>,
+    /// The keys written, if serializable transactions must check conflicts against them.
+    write_set: Option<ConflictSet>,
+    finished: bool,
+    _pending_outputs: PendingOutputs<'a>,
+    _write_lock: MutexGuard<'a, ()>,
+    _commit_lock: MutexGuard<'a, ()>,
+}
+
+impl LsmStorageInner {
+    pub(crate) fn bulk_load(&self) -> BulkLoad<'_> {
+        self.compaction_pauses.fetch_add(1, Ordering::SeqCst);
+        let mvcc = self.mvcc();
+        let commit_lock = mvcc.commit_lock.lock();
+        let write_lock = mvcc.write_lock.lock();
+        BulkLoad {
+            inner: self,
+            ts: mvcc.latest_commit_ts() + 1,
+            builder: None,
+            tables: Vec::new(),
+            last_key: None,
+            write_set: mvcc
+                .has_serializable_txns()
+                .then(|| ConflictSet::new(self.options.conflict_tracking_limit)),
+            finished: false,
+            _pending_outputs: self.protect_pending_outputs(),
+            _write_lock: write_lock,
+            _commit_lock: commit_lock,
+        }
+    }
+}
+
+impl BulkLoad<'_> {
+    /// The commit timestamp of the loaded keys.
+    pub fn ts(&self) -> u64 {
+        self.ts
+    }
+
+    /// Add a key, which must be larger than all keys added before.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            bail!("key cannot be empty");
+        }
+        if value.is_empty() {
+            bail!("value cannot be empty");
+        }
+        if self.last_key.as_deref().is_some_and(|last| last >= key) {
+            bail!("keys must be added in increasing order");
+        }
+        if self
+            .builder
+            .as_ref()
+            .is_some_and(|builder| builder.estimated_size() >= self.inner.options.target_sst_size)
+        {
+            self.finish_sst()?;
+        }
+        self.builder
+            .get_or_insert_with(|| SsTableBuilder::new(self.inner.options.block_size))
+            .add(KeySlice::from_slice(key, self.ts), value);
+        if let Some(write_set) = &mut self.write_set {
+            write_set.add_write(key);
+        }
+        match &mut self.last_key {
+            Some(last_key) => {
+                last_key.clear();
+                last_key.extend(key);
+            }
+            None => self.last_key = Some(key.to_vec()),
+        }
+        Ok(())
+    }
+
+    /// Add a stream of sorted key-value pairs, whose keys are larger than all keys added before.
+    pub fn write_stream<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        stream: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<()> {
+        for (key, value) in stream {
+            self.put(key.as_ref(), value.as_ref())?;
+        }
+        Ok(())
+    }
+
+    fn finish_sst(&mut self) -> Result<()> {
+        if let Some(builder) = self.builder.take() {
+            let sst_id = self.inner.next_sst_id();
+            self.tables.push(Arc::new(builder.build(
+                sst_id,
+                Some(self.inner.block_cache.clone()),
+                self.inner.path_of_sst(sst_id),
+            )?));
+        }
+        Ok(())
+    }
+
+    /// Publish the loaded SSTs in the bottom level with a single manifest record, and return the commit timestamp.
+    /// With tiered compaction, they form the last tier.
+    pub fn finish(mut self) -> Result<u64> {
+        self.finish_sst()?;
+        let inner = self.inner;
+        if self.tables.is_empty() {
+            self.finished = true;
+            return Ok(self.ts);
+        }
+        let rotated = {
+            // a compaction output may otherwise overlap the loaded SSTs
+            let _compaction_lock = inner.compaction_lock.lock();
+            let state_lock = inner.state_lock.lock();
+            let mut snapshot = inner.state.read().as_ref().clone();
+            let ids = self
+                .tables
+                .iter()
+                .map(|table| table.sst_id())
+                .collect::<Vec<_>>();
+            if inner.compaction_controller.flush_to_l0() {
+                let first_key = self.tables.first().unwrap().first_key();
+                let last_key = self.tables.last().unwrap().last_key();
+                let sstables = &snapshot.sstables;
+                let bottom = &mut snapshot.levels.last_mut().unwrap().1;
+                if bottom.iter().any(|id| {
+                    sstables[id].first_key().key_ref() <= last_key.key_ref()
+                        && first_key.key_ref() <= sstables[id].last_key().key_ref()
+                }) {
+                    bail!("the loaded keys overlap the bottom level");
+                }
+                let pos = bottom.partition_point(|id| sstables[id].first_key() < first_key);
+                bottom.splice(pos..pos, ids);
+            } else {
+                snapshot.levels.push((ids[0], ids));
+            }
+            for table in &self.tables {
+                snapshot.sstables.insert(table.sst_id(), table.clone());
+            }
+            let mut edit = VersionEdit::diff(&inner.state.read(), &snapshot);
+            edit.commit_ts = Some(self.ts);
+            // the SSTs are only published once recorded, otherwise they are removed on drop
+            inner.sync_dir()?;
+            inner.manifest().add_record(&state_lock, edit)?;
+            *inner.state.write() = Arc::new(snapshot);
+            self.finished = true;
+            // the SSTs are published, so the commit ts must be used even if the rotation fails
+            inner.maybe_rotate_manifest(&state_lock)
+        };
+
+        let mvcc = inner.mvcc();
+        if let Some(mut write_set) = self.write_set.take() {
+            mvcc.record_commit(self.ts, self.ts - 1, write_set.take_write_set());
+        }
+        mvcc.update_commit_ts(self.ts);
+        rotated?;
+        println!(
+            "bulk loaded {} SSTs at ts {}: {:?}",
+            self.tables.len(),
+            self.ts,
+            self.tables
+                .iter()
+                .map(|table| table.sst_id())
+                .collect::<Vec<_>>()
+        );
+        Ok(self.ts)
+    }
+}
+
+impl Drop for BulkLoad<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            for table in &self.tables {
+                std::fs::remove_file(self.inner.path_of_sst(table.sst_id())).ok();
+            }
+        }
+        self.inner.compaction_pauses.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::lsm_storage::LsmStorageInner;
+use crate::manifest::{Manifest, VersionEdit};
+use crate::table::SsTableBuilder;
+
+impl LsmStorageInner {
+    /// Create a copy of the database in `dir` that can be opened on its own. SSTs are immutable, so they are hard
+    /// linked. With the WAL enabled, the memtables are captured by copying the WAL, unless they hold writes skipping
+    /// the WAL; otherwise their entries committed so far are written to new SSTs in `dir`. Returns the latest commit
+    /// timestamp recorded in the copy.
+    pub(crate) fn checkpoint(&self, dir: &Path) -> Result<u64> {
+        if dir.exists() && std::fs::read_dir(dir)?.next().is_some() {
+            bail!("checkpoint directory {:?} is not empty", dir);
+        }
+        std::fs::create_dir_all(dir)?;
+
+        // flushes and compactions hold the state lock, so the SSTs of the state are not removed while linked
+        let _state_lock = self.state_lock.lock();
+        let commit_ts = self.mvcc().latest_commit_ts();
+        let mut state = self.state.read().as_ref().clone();
+        for id in state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, files)| files))
+        {
+            let src = self.path_of_sst(*id);
+            let dest = Self::path_of_sst_static(dir, *id);
+            if std::fs::hard_link(&src, &dest).is_err() {
+                // e.g. the checkpoint is on another file system
+                std::fs::copy(&src, &dest)?;
+                File::open(&dest)?.sync_all()?;
+            }
+        }
+
+        // from the oldest memtable, so that the latest one ends up first
+        let memtables = state
+            .imm_memtables
+            .iter()
+            .rev()
+            .chain(std::iter::once(&state.memtable))
+            .cloned()
+            .collect::<Vec<_>>();
+        let wal = self
+            .wal
+            .as_ref()
+            .filter(|_| !memtables.iter().any(|memtable| memtable.has_unlogged()));
+        let flushed_lsn = match wal {
+            Some(wal) => {
+                wal.copy_to(dir)?;
+                wal.flushed_lsn()
+            }
+            None => {
+                for memtable in memtables {
+                    let mut builder = SsTableBuilder::new(self.options.block_size);
+                    let mut empty = true;
+                    // a commit being written has a timestamp above `commit_ts`, and is skipped as a whole
+                    for entry in memtable.map.iter().filter(|e| e.key().ts() <= commit_ts) {
+                        builder.add(entry.key().as_key_slice(), &entry.value()[..]);
+                        empty = false;
+                    }
+                    if empty {
+                        continue;
+                    }
+                    // a new id, as the memtable is flushed to an SST of its own id later
+                    let sst_id = self.next_sst_id();
+                    let sst = builder.build(sst_id, None, Self::path_of_sst_static(dir, sst_id))?;
+                    if self.compaction_controller.flush_to_l0() {
+                        state.l0_sstables.insert(0, sst_id);
+                    } else {
+                        state.levels.insert(0, (sst_id, vec![sst_id]));
+                    }
+                    state.sstables.insert(sst_id, Arc::new(sst));
+                }
+                0
+            }
+        };
+
+        let snapshot = VersionEdit::snapshot(
+            &state,
+            self.next_sst_id.load(Ordering::SeqCst),
+            commit_ts,
+            flushed_lsn,
+        );
+        Manifest::write_snapshot(dir, snapshot)?;
+        File::open(dir)?.sync_all()?;
+        Ok(commit_ts)
+    }
+}
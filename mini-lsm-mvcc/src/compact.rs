@@ -3,6 +3,7 @@ mod simple_leveled;
 mod tiered;
 
 use std::collections::HashSet;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -20,7 +21,7 @@ use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::key::KeySlice;
 use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
-use crate::manifest::ManifestRecord;
+use crate::manifest::VersionEdit;
 use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -120,7 +121,7 @@ impl LsmStorageInner {
     ) -> Result<Vec<Arc<SsTable>>> {
         let mut builder = None;
         let mut new_sst = Vec::new();
-        let watermark = self.mvcc().watermark();
+        let watermark = self.mvcc().gc_watermark();
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
@@ -301,6 +302,7 @@ impl LsmStorageInner {
             panic!("full compaction can only be called with compaction is not enabled")
         };
 
+        let _compaction_lock = self.compaction_lock.lock();
         let snapshot = {
             let state = self.state.read();
             state.clone()
@@ -315,6 +317,8 @@ impl LsmStorageInner {
 
         println!("force full compaction: {:?}", compaction_task);
 
+        let _pending_outputs = self.protect_pending_outputs();
+
         let sstables = self.compact(&compaction_task)?;
         let mut ids = Vec::with_capacity(sstables.len());
 
@@ -340,15 +344,17 @@ impl LsmStorageInner {
                 .copied()
                 .collect::<Vec<_>>();
             assert!(l0_sstables_map.is_empty());
+            let edit = VersionEdit::diff(&self.state.read(), &state);
             *self.state.write() = Arc::new(state);
             self.sync_dir()?;
-            self.manifest.as_ref().unwrap().add_record(
-                &state_lock,
-                ManifestRecord::Compaction(compaction_task, ids.clone()),
-            )?;
+            self.manifest
+                .as_ref()
+                .unwrap()
+                .add_record(&state_lock, edit)?;
+            self.maybe_rotate_manifest(&state_lock)?;
         }
         for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
-            std::fs::remove_file(self.path_of_sst(*sst))?;
+            self.remove_sst_file(*sst)?;
         }
 
         println!("force full compaction done, new SSTs: {:?}", ids);
@@ -357,6 +363,10 @@ impl LsmStorageInner {
     }
 
     fn trigger_compaction(&self) -> Result<()> {
+        let _compaction_lock = self.compaction_lock.lock();
+        if self.compaction_pauses.load(Ordering::SeqCst) > 0 {
+            return Ok(());
+        }
         let snapshot = {
             let state = self.state.read();
             state.clone()
@@ -369,6 +379,7 @@ impl LsmStorageInner {
         };
         self.dump_structure();
         println!("running compaction task: {:?}", task);
+        let _pending_outputs = self.protect_pending_outputs();
         let sstables = self.compact(&task)?;
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
         let ssts_to_remove = {
@@ -391,11 +402,12 @@ impl LsmStorageInner {
                 ssts_to_remove.push(result.unwrap());
             }
             let mut state = self.state.write();
+            let edit = VersionEdit::diff(&state, &snapshot);
             *state = Arc::new(snapshot);
             drop(state);
             self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
+            self.manifest().add_record(&state_lock, edit)?;
+            self.maybe_rotate_manifest(&state_lock)?;
             ssts_to_remove
         };
         println!(
@@ -405,7 +417,7 @@ impl LsmStorageInner {
             output
         );
         for sst in ssts_to_remove {
-            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
+            self.remove_sst_file(sst.sst_id())?;
         }
         self.sync_dir()?;
 
@@ -456,11 +468,18 @@ impl LsmStorageInner {
         let this = self.clone();
         let handle = std::thread::spawn(move || {
             let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+            let gc_ticker = match this.options.gc_interval {
+                Some(interval) => crossbeam_channel::tick(interval),
+                None => crossbeam_channel::never(),
+            };
             loop {
                 crossbeam_channel::select! {
                     recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
                         eprintln!("flush failed: {}", e);
                     },
+                    recv(gc_ticker) -> _ => if let Err(e) = this.collect_garbage(false) {
+                        eprintln!("garbage collection failed: {}", e);
+                    },
                     recv(rx) -> _ => return
                 }
             }
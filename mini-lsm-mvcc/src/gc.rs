@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+
+use crate::lsm_storage::LsmStorageInner;
+
+/// Files removed by a garbage collection.
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    pub removed_files: Vec<String>,
+    pub reclaimed_bytes: u64,
+}
+
+/// Protects the SSTs written by a compaction from garbage collection until they are installed in the state.
+pub(crate) struct PendingOutputs<'a> {
+    inner: &'a LsmStorageInner,
+    min_id: usize,
+}
+
+impl Drop for PendingOutputs<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.inner.pending_outputs.lock();
+        let idx = pending.iter().position(|x| *x == self.min_id).unwrap();
+        pending.swap_remove(idx);
+    }
+}
+
+impl LsmStorageInner {
+    /// Protect the SSTs allocated from now on from garbage collection, until the guard is dropped.
+    pub(crate) fn protect_pending_outputs(&self) -> PendingOutputs<'_> {
+        let mut pending = self.pending_outputs.lock();
+        let min_id = self.next_sst_id.load(Ordering::SeqCst);
+        pending.push(min_id);
+        PendingOutputs {
+            inner: self,
+            min_id,
+        }
+    }
+
+    /// Remove an SST replaced by a compaction, which may have been collected already.
+    pub(crate) fn remove_sst_file(&self, id: usize) -> Result<()> {
+        match std::fs::remove_file(self.path_of_sst(id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove the files in the DB directory that are no longer referenced: SSTs not in the state and not being
+    /// written, WAL segments not used by the WAL, and manifests other than the current one. WAL segments are kept
+    /// when the WAL is disabled, and spilled transaction writes are only removed on open, as they are owned by
+    /// running transactions otherwise.
+    pub(crate) fn collect_garbage(&self, on_open: bool) -> Result<GcReport> {
+        // flushes and manifest rotations hold the state lock, so their files are always referenced
+        let _state_lock = self.state_lock.lock();
+        // compactions protect their outputs before allocating ids, so the next id must be read first
+        let next_sst_id = self.next_sst_id.load(Ordering::SeqCst);
+        let min_pending = self.pending_outputs.lock().iter().min().copied();
+        // ids from the next one on are not recorded as allocated by the manifest, so their SSTs may be written by a
+        // flush or compaction that is yet to be recorded. Orphans of a crash there are overwritten when the id is
+        // allocated again, or removed once the allocated ids pass them.
+        let sst_limit = min_pending.map_or(next_sst_id, |id| id.min(next_sst_id));
+        let live_ssts = {
+            let state = self.state.read();
+            state
+                .l0_sstables
+                .iter()
+                .chain(state.levels.iter().flat_map(|(_, files)| files))
+                .copied()
+                .collect::<HashSet<_>>()
+        };
+        let manifest = self.manifest().file_name();
+
+        let mut report = GcReport::default();
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let obsolete = match name.rsplit_once('.') {
+                Some((id, "sst")) => id
+                    .parse::<usize>()
+                    .is_ok_and(|id| id < sst_limit && !live_ssts.contains(&id)),
+                // without the WAL, the segments may still hold unflushed writes for a later open with the WAL
+                Some((id, "wal")) => self
+                    .wal
+                    .as_ref()
+                    .is_some_and(|wal| id.parse::<u32>().is_ok_and(|id| !wal.is_live_segment(id))),
+                Some((_, "spill")) => on_open,
+                Some(("CURRENT", "tmp")) => true,
+                _ => name.starts_with("MANIFEST-") && name != manifest,
+            };
+            if !obsolete {
+                continue;
+            }
+            let size = entry.metadata()?.len();
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => {}
+                // removed by its owner in the meantime
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+            report.removed_files.push(name);
+            report.reclaimed_bytes += size;
+        }
+        if !report.removed_files.is_empty() {
+            self.sync_dir()?;
+            println!(
+                "garbage collection removed {} files, {} bytes: {:?}",
+                report.removed_files.len(),
+                report.reclaimed_bytes,
+                report.removed_files
+            );
+        }
+        Ok(report)
+    }
+}
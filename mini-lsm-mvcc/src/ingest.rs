@@ -0,0 +1,228 @@
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_DEFAULT, TS_RANGE_BEGIN, TS_RANGE_END};
+use crate::lsm_storage::LsmStorageInner;
+use crate::manifest::VersionEdit;
+use crate::mvcc::conflict::ConflictSet;
+use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+/// Writes a sorted SST outside of a database, which can then be added to a database with `MiniLsm::ingest`. Keys
+/// are written without a timestamp, the ingestion assigns a commit timestamp to all of them.
+pub struct SstFileWriter {
+    builder: SsTableBuilder,
+    path: PathBuf,
+    last_key: Option<Vec<u8>>,
+}
+
+impl SstFileWriter {
+    pub fn create(path: impl AsRef<Path>, block_size: usize) -> Self {
+        Self {
+            builder: SsTableBuilder::new(block_size),
+            path: path.as_ref().to_path_buf(),
+            last_key: None,
+        }
+    }
+
+    /// Add a key, which must be larger than the previous one.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if value.is_empty() {
+            bail!("value cannot be empty");
+        }
+        self.add(key, value)
+    }
+
+    /// Add a deletion of a key, which must be larger than the previous one.
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.add(key, b"")
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            bail!("key cannot be empty");
+        }
+        if self.last_key.as_deref().is_some_and(|last| last >= key) {
+            bail!("keys must be added in increasing order");
+        }
+        self.builder
+            .add(KeySlice::from_slice(key, TS_DEFAULT), value);
+        self.last_key = Some(key.to_vec());
+        Ok(())
+    }
+
+    /// Write the SST file.
+    pub fn finish(self) -> Result<()> {
+        if self.last_key.is_none() {
+            bail!("cannot write an empty SST");
+        }
+        self.builder.build(0, None, &self.path)?;
+        Ok(())
+    }
+}
+
+fn overlaps(a: &SsTable, b: &SsTable) -> bool {
+    a.first_key().key_ref() <= b.last_key().key_ref()
+        && b.first_key().key_ref() <= a.last_key().key_ref()
+}
+
+impl LsmStorageInner {
+    /// Add SSTs written by `SstFileWriter`, whose key ranges must not overlap each other. All keys are committed at
+    /// one new timestamp: the files are copied into the database with that timestamp, and recorded in one manifest
+    /// record. The memtables are flushed first if they overlap the files, so that older versions are never flushed
+    /// above the files. With leveled compaction or without compaction, each file goes to the lowest level it overlaps
+    /// no SST in or above, or to L0 if it overlaps L0. With tiered compaction, the files form a new tier. Writes wait
+    /// for the copy to finish.
+    pub(crate) fn ingest(&self, files: &[PathBuf]) -> Result<u64> {
+        if files.is_empty() {
+            bail!("no files to ingest");
+        }
+        let mut external = Vec::with_capacity(files.len());
+        for path in files {
+            let table = SsTable::open(0, None, FileObject::open(path)?)?;
+            if table.max_ts() != TS_DEFAULT {
+                bail!("{:?} was not written by SstFileWriter", path);
+            }
+            external.push(table);
+        }
+        external.sort_by(|a, b| a.first_key().key_ref().cmp(b.first_key().key_ref()));
+        if external.windows(2).any(|pair| overlaps(&pair[0], &pair[1])) {
+            bail!("ingested files overlap each other");
+        }
+
+        let _pending_outputs = self.protect_pending_outputs();
+        let mvcc = self.mvcc();
+        let _commit_lock = mvcc.commit_lock.lock();
+        let _write_lock = mvcc.write_lock.lock();
+        self.flush_overlapping_memtables(&external)?;
+        let ts = mvcc.latest_commit_ts() + 1;
+        let track_writes = mvcc.has_serializable_txns();
+        let mut write_set = ConflictSet::new(self.options.conflict_tracking_limit);
+        let mut tables = Vec::with_capacity(external.len());
+        for table in external {
+            let mut builder = SsTableBuilder::new(self.options.block_size);
+            let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(table))?;
+            while iter.is_valid() {
+                let key = iter.key().key_ref();
+                builder.add(KeySlice::from_slice(key, ts), iter.value());
+                if track_writes {
+                    write_set.add_write(key);
+                }
+                iter.next()?;
+            }
+            let sst_id = self.next_sst_id();
+            tables.push(Arc::new(builder.build(
+                sst_id,
+                Some(self.block_cache.clone()),
+                self.path_of_sst(sst_id),
+            )?));
+        }
+
+        let rotated = {
+            // a file must not be placed within the key range of a running compaction
+            let _compaction_lock = self.compaction_lock.lock();
+            let state_lock = self.state_lock.lock();
+            let mut snapshot = self.state.read().as_ref().clone();
+            if self.compaction_controller.flush_to_l0() {
+                for table in &tables {
+                    let overlaps_level = |ids: &[usize]| {
+                        ids.iter().any(|id| overlaps(&snapshot.sstables[id], table))
+                    };
+                    let level = if overlaps_level(&snapshot.l0_sstables) {
+                        None
+                    } else {
+                        snapshot
+                            .levels
+                            .iter()
+                            .take_while(|(_, ids)| !overlaps_level(ids))
+                            .count()
+                            .checked_sub(1)
+                    };
+                    match level {
+                        Some(level) => {
+                            let pos = snapshot.levels[level].1.partition_point(|id| {
+                                snapshot.sstables[id].first_key() < table.first_key()
+                            });
+                            snapshot.levels[level].1.insert(pos, table.sst_id());
+                        }
+                        None => snapshot.l0_sstables.insert(0, table.sst_id()),
+                    }
+                    snapshot.sstables.insert(table.sst_id(), table.clone());
+                }
+            } else {
+                let ids = tables
+                    .iter()
+                    .map(|table| table.sst_id())
+                    .collect::<Vec<_>>();
+                snapshot.levels.insert(0, (ids[0], ids));
+                for table in &tables {
+                    snapshot.sstables.insert(table.sst_id(), table.clone());
+                }
+            }
+            let mut edit = VersionEdit::diff(&self.state.read(), &snapshot);
+            edit.commit_ts = Some(ts);
+            // the files are only published once recorded, otherwise they are left to garbage collection
+            self.sync_dir()?;
+            self.manifest().add_record(&state_lock, edit)?;
+            *self.state.write() = Arc::new(snapshot);
+            // the files are published, so the commit ts must be used even if the rotation fails
+            self.maybe_rotate_manifest(&state_lock)
+        };
+
+        if track_writes {
+            mvcc.record_commit(ts, ts - 1, write_set.take_write_set());
+        }
+        mvcc.update_commit_ts(ts);
+        rotated?;
+        println!(
+            "ingested {} files at ts {}: {:?}",
+            tables.len(),
+            ts,
+            tables
+                .iter()
+                .map(|table| table.sst_id())
+                .collect::<Vec<_>>()
+        );
+        Ok(ts)
+    }
+
+    /// Flush all memtables if any of them has a key in the range of a file. The caller holds the write lock, so
+    /// that no overlapping write is put into the memtables afterwards.
+    fn flush_overlapping_memtables(&self, files: &[SsTable]) -> Result<()> {
+        let overlapping = {
+            let state = self.state.read();
+            std::iter::once(&state.memtable)
+                .chain(state.imm_memtables.iter())
+                .any(|memtable| {
+                    files.iter().any(|file| {
+                        memtable
+                            .scan(
+                                Bound::Included(KeySlice::from_slice(
+                                    file.first_key().key_ref(),
+                                    TS_RANGE_BEGIN,
+                                )),
+                                Bound::Included(KeySlice::from_slice(
+                                    file.last_key().key_ref(),
+                                    TS_RANGE_END,
+                                )),
+                            )
+                            .is_valid()
+                    })
+                })
+        };
+        if !overlapping {
+            return Ok(());
+        }
+        if !self.state.read().memtable.is_empty() {
+            self.force_freeze_memtable(&self.state_lock.lock())?;
+        }
+        // the older memtables are flushed first, as the flush order is the order of L0
+        while !self.state.read().imm_memtables.is_empty() {
+            self.force_flush_next_imm_memtable()?;
+        }
+        Ok(())
+    }
+}
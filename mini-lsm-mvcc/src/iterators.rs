@@ -3,7 +3,7 @@ pub mod merge_iterator;
 pub mod two_merge_iterator;
 
 pub trait StorageIterator {
-    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
+    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord + Copy
     where
         Self: 'a;
 
@@ -19,6 +19,10 @@ pub trait StorageIterator {
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// Re-position the iterator at the first entry that is >= `key`. The key should be inside the range
+    /// the iterator was created with, as sources outside of that range may have been pruned on creation.
+    fn seek(&mut self, key: Self::KeyType<'_>) -> anyhow::Result<()>;
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
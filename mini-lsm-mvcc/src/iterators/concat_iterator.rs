@@ -1,10 +1,12 @@
+use std::ops::Bound;
 use std::sync::Arc;
 
 use anyhow::Result;
+use bytes::Bytes;
 
 use crate::{
     key::KeySlice,
-    table::{SsTable, SsTableIterator},
+    table::{key_within_upper_bound, SsTable, SsTableIterator},
 };
 
 use super::StorageIterator;
@@ -15,6 +17,8 @@ pub struct SstConcatIterator {
     current: Option<SsTableIterator>,
     next_sst_idx: usize,
     sstables: Vec<Arc<SsTable>>,
+    /// The iterator stops at this bound, and SSTs that start after it are never opened.
+    upper: Bound<Bytes>,
 }
 
 impl SstConcatIterator {
@@ -36,6 +40,7 @@ impl SstConcatIterator {
                 current: None,
                 next_sst_idx: 0,
                 sstables,
+                upper: Bound::Unbounded,
             });
         }
         let mut iter = Self {
@@ -44,6 +49,7 @@ impl SstConcatIterator {
             )?),
             next_sst_idx: 1,
             sstables,
+            upper: Bound::Unbounded,
         };
         iter.move_until_valid()?;
         Ok(iter)
@@ -51,39 +57,66 @@ impl SstConcatIterator {
 
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
         Self::check_sst_valid(&sstables);
-        let idx: usize = sstables
-            .partition_point(|table| table.first_key().as_key_slice() <= key)
-            .saturating_sub(1);
-        if idx >= sstables.len() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: sstables.len(),
-                sstables,
-            });
-        }
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_key(
-                sstables[idx].clone(),
-                key,
-            )?),
-            next_sst_idx: idx + 1,
+            current: None,
+            next_sst_idx: 0,
             sstables,
+            upper: Bound::Unbounded,
         };
-        iter.move_until_valid()?;
+        iter.seek_to_key(key)?;
+        Ok(iter)
+    }
+
+    /// Stop the iterator at the upper bound. SSTs and blocks that start after the bound will not be read.
+    pub fn with_upper_bound(mut self, upper: Bound<&[u8]>) -> Result<Self> {
+        self.upper = upper.map(Bytes::copy_from_slice);
+        if let Some(current) = self.current.as_mut() {
+            current.set_upper_bound(upper);
+        }
+        self.move_until_valid()?;
+        Ok(self)
+    }
+
+    fn create_sst_iter(&self, idx: usize, key: Option<KeySlice>) -> Result<SsTableIterator> {
+        let table = self.sstables[idx].clone();
+        let mut iter = match key {
+            Some(key) => SsTableIterator::create_and_seek_to_key(table, key)?,
+            None => SsTableIterator::create_and_seek_to_first(table)?,
+        };
+        iter.set_upper_bound(self.upper.as_ref().map(|x| x.as_ref()));
         Ok(iter)
     }
 
+    /// Seek to the first key-value pair which >= `key`, without re-validating the SST list.
+    pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        let idx: usize = self
+            .sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key)
+            .saturating_sub(1);
+        if idx >= self.sstables.len() {
+            self.current = None;
+            self.next_sst_idx = self.sstables.len();
+            return Ok(());
+        }
+        self.current = Some(self.create_sst_iter(idx, Some(key))?);
+        self.next_sst_idx = idx + 1;
+        self.move_until_valid()
+    }
+
     fn move_until_valid(&mut self) -> Result<()> {
         while let Some(iter) = self.current.as_mut() {
             if iter.is_valid() {
                 break;
             }
-            if self.next_sst_idx >= self.sstables.len() {
+            if self.next_sst_idx >= self.sstables.len()
+                || !key_within_upper_bound(
+                    self.sstables[self.next_sst_idx].first_key().key_ref(),
+                    self.upper.as_ref().map(|x| x.as_ref()),
+                )
+            {
                 self.current = None;
             } else {
-                self.current = Some(SsTableIterator::create_and_seek_to_first(
-                    self.sstables[self.next_sst_idx].clone(),
-                )?);
+                self.current = Some(self.create_sst_iter(self.next_sst_idx, None)?);
                 self.next_sst_idx += 1;
             }
         }
@@ -117,6 +150,10 @@ impl StorageIterator for SstConcatIterator {
         Ok(())
     }
 
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_to_key(key)
+    }
+
     fn num_active_iterators(&self) -> usize {
         1
     }
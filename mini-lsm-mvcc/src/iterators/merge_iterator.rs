@@ -39,39 +39,51 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// Iterators that ran out of entries (or errored), kept around so that `seek` can revive them.
+    exhausted: Vec<HeapWrapper<I>>,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: Vec::new(),
+        };
+        iter.rebuild(
+            iters
+                .into_iter()
+                .enumerate()
+                .map(|(idx, iter)| HeapWrapper(idx, iter))
+                .collect(),
+        );
+        iter
+    }
+
+    /// Re-create the heap from a set of iterators that have been (re-)positioned.
+    fn rebuild(&mut self, mut iters: Vec<HeapWrapper<I>>) {
         if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
+            return;
         }
 
-        let mut heap = BinaryHeap::new();
+        iters.sort_by_key(|x| x.0);
 
-        if iters.iter().all(|x| !x.is_valid()) {
+        if iters.iter().all(|x| !x.1.is_valid()) {
             // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
+            self.current = iters.pop();
+            self.exhausted = iters;
+            return;
         }
 
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+        for iter in iters {
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
             }
         }
 
-        let current = heap.pop().unwrap();
-        Self {
-            iters: heap,
-            current: Some(current),
-        }
+        self.current = self.iters.pop();
     }
 }
 
@@ -106,13 +118,13 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
             if inner_iter.1.key() == current.1.key() {
                 // Case 1: an error occurred when calling `next`.
                 if let e @ Err(_) = inner_iter.1.next() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                     return e;
                 }
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
@@ -124,7 +136,7 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
             if let Some(iter) = self.iters.pop() {
-                *current = iter;
+                self.exhausted.push(std::mem::replace(current, iter));
             }
             return Ok(());
         }
@@ -139,6 +151,26 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         Ok(())
     }
 
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let mut iters = std::mem::take(&mut self.exhausted);
+        iters.extend(self.iters.drain());
+        iters.extend(self.current.take());
+        let mut result = Ok(());
+        for iter in &mut iters {
+            if let e @ Err(_) = iter.1.seek(key) {
+                result = e;
+                break;
+            }
+        }
+        if result.is_err() {
+            // Leave the merge iterator in an invalid state, `seek` can be retried afterwards.
+            self.exhausted = iters;
+            return result;
+        }
+        self.rebuild(iters);
+        Ok(())
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.iters
             .iter()
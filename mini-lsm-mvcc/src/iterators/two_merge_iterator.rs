@@ -88,6 +88,14 @@ impl<
         Ok(())
     }
 
+    fn seek(&mut self, key: A::KeyType<'_>) -> Result<()> {
+        self.a.seek(key)?;
+        self.b.seek(key)?;
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b);
+        Ok(())
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.a.num_active_iterators() + self.b.num_active_iterators()
     }
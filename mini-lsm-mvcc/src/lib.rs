@@ -1,6 +1,11 @@
+pub mod backup;
 pub mod block;
+pub mod bulk_load;
+pub mod checkpoint;
 pub mod compact;
 pub mod debug;
+pub mod gc;
+pub mod ingest;
 pub mod iterators;
 pub mod key;
 pub mod lsm_iterator;
@@ -10,6 +15,7 @@ pub mod mem_table;
 pub mod mvcc;
 pub mod table;
 pub mod wal;
+pub mod watch;
 
 #[cfg(test)]
 mod tests;
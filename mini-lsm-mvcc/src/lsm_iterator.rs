@@ -7,11 +7,12 @@ use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
+use crate::key::{self, KeySlice};
 use crate::mem_table::MemTableIterator;
 use crate::table::SsTableIterator;
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
-type LsmIteratorInner = TwoMergeIterator<
+pub(crate) type LsmIteratorInner = TwoMergeIterator<
     TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>,
     MergeIterator<SstConcatIterator>,
 >;
@@ -31,27 +32,33 @@ impl LsmIterator {
         read_ts: u64,
     ) -> Result<Self> {
         let mut iter = Self {
-            is_valid: iter.is_valid(),
+            is_valid: false,
             inner: iter,
             end_bound,
             read_ts,
             prev_key: Vec::new(),
         };
+        iter.update_valid();
         iter.move_to_key()?;
         Ok(iter)
     }
 
-    fn next_inner(&mut self) -> Result<()> {
-        self.inner.next()?;
+    /// Check the position of the inner iterator against the end bound.
+    fn update_valid(&mut self) {
         if !self.inner.is_valid() {
             self.is_valid = false;
-            return Ok(());
-        }
-        match self.end_bound.as_ref() {
-            Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
+            return;
         }
+        self.is_valid = match self.end_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(key) => self.inner.key().key_ref() <= key.as_ref(),
+            Bound::Excluded(key) => self.inner.key().key_ref() < key.as_ref(),
+        };
+    }
+
+    fn next_inner(&mut self) -> Result<()> {
+        self.inner.next()?;
+        self.update_valid();
         Ok(())
     }
 
@@ -106,6 +113,14 @@ impl StorageIterator for LsmIterator {
         Ok(())
     }
 
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.inner
+            .seek(KeySlice::from_slice(key, key::TS_RANGE_BEGIN))?;
+        self.prev_key.clear();
+        self.update_valid();
+        self.move_to_key()
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.inner.num_active_iterators()
     }
@@ -163,6 +178,17 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         Ok(())
     }
 
+    fn seek(&mut self, key: Self::KeyType<'_>) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek(key) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.iter.num_active_iterators()
     }
@@ -1,30 +1,40 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::HashMap;
 use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
 use crate::block::Block;
+use crate::bulk_load::BulkLoad;
 use crate::compact::{
     CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
 };
+use crate::gc::GcReport;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::key::{self, KeySlice};
-use crate::lsm_iterator::{FusedIterator, LsmIterator};
-use crate::manifest::{Manifest, ManifestRecord};
+use crate::lsm_iterator::{FusedIterator, LsmIterator, LsmIteratorInner};
+use crate::manifest::{LegacyManifestRecord, Manifest, Version, VersionEdit};
 use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
-use crate::mvcc::txn::{Transaction, TxnIterator};
+use crate::mvcc::changes::ChangeIterator;
+use crate::mvcc::commit_queue::CommitRequest;
+use crate::mvcc::conflict::KeySet;
+use crate::mvcc::snapshot::{Snapshot, SnapshotIterator};
+use crate::mvcc::tailing::TailingIterator;
+use crate::mvcc::txn::{IsolationLevel, Transaction, TxnIterator, TxnOptions};
 use crate::mvcc::LsmMvccInner;
 use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::wal::{Wal, WalBatch, WalEntryKind, WalRecoveryReport, TXN_CHUNK_SIZE};
+use crate::watch::{Watcher, Watchers, DEFAULT_WATCH_CAPACITY};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
@@ -49,6 +59,23 @@ pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Del(T),
 }
 
+/// Options of a single write or transaction commit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Do not return before the write is on stable storage. Has no effect if the WAL is not enabled.
+    pub sync: bool,
+    /// Skip the WAL, the write is lost if the process crashes before its memtable is flushed.
+    pub disable_wal: bool,
+}
+
+impl<T: AsRef<[u8]>> WriteBatchRecord<T> {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            WriteBatchRecord::Put(key, _) | WriteBatchRecord::Del(key) => key.as_ref(),
+        }
+    }
+}
+
 impl LsmStorageState {
     fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
@@ -81,6 +108,70 @@ pub struct LsmStorageOptions {
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Keep old versions for time-travel reads, in addition to the versions needed by live readers
+    pub history_retention: Option<HistoryRetention>,
+    // Use pessimistic locking for transactions, waiting at most this long for a lock. Transactions use optimistic
+    // concurrency control if not set. Writes outside of transactions do not take locks.
+    pub lock_timeout: Option<Duration>,
+    // Serializable transactions track the exact keys and scanned ranges they read and write up to this many bytes,
+    // and fall back to key hashes beyond it, which may cause false-positive conflicts
+    pub conflict_tracking_limit: usize,
+    // Transactions spill their local writes to sorted runs on disk once they grow beyond this many bytes, and stream
+    // them from there into the memtable and WAL when they commit
+    pub txn_spill_threshold: usize,
+    // The WAL shared by all memtables starts a new segment file once the current one grows beyond this many bytes
+    pub wal_segment_size: usize,
+    // How to recover a WAL with corrupted or incomplete entries
+    pub wal_recovery_mode: WalRecoveryMode,
+    // Remove files no longer referenced by the state at this interval, in addition to on open
+    pub gc_interval: Option<Duration>,
+    // The manifest is rotated, starting a new file with a snapshot of the state, once it grows beyond this many bytes
+    pub manifest_rotation_size: usize,
+}
+
+/// How much history compaction keeps for time-travel reads.
+#[derive(Debug, Clone, Copy)]
+pub enum HistoryRetention {
+    /// Keep all versions visible to commits in the given time window.
+    Duration(Duration),
+    /// Keep all versions visible to the last K commit timestamps.
+    Commits(u64),
+}
+
+/// How the WAL is recovered when some of its entries are corrupted or cut off by a crash.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalRecoveryMode {
+    /// Fail to open on any corrupted or incomplete entry.
+    AbsoluteConsistency,
+    /// Drop corrupted and incomplete entries at the end of a segment, as left behind by a crash, and fail to open
+    /// on any other corruption.
+    #[default]
+    TolerateCorruptedTailRecords,
+    /// Recover up to the first corrupted entry or missing LSN, and drop everything after it.
+    PointInTime,
+    /// Skip all corrupted entries and recover everything else.
+    SkipAnyCorruptedRecords,
+}
+
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            target_sst_size: 2 << 20,
+            compaction_options: CompactionOptions::NoCompaction,
+            enable_wal: false,
+            num_memtable_limit: 3,
+            serializable: false,
+            history_retention: None,
+            lock_timeout: None,
+            conflict_tracking_limit: 1 << 20,
+            txn_spill_threshold: 64 << 20,
+            wal_segment_size: 1 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+            manifest_rotation_size: 4 << 20,
+            gc_interval: Some(Duration::from_secs(60)),
+        }
+    }
 }
 
 impl LsmStorageOptions {
@@ -92,6 +183,14 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            history_retention: None,
+            lock_timeout: None,
+            conflict_tracking_limit: 1 << 20,
+            txn_spill_threshold: 64 << 20,
+            wal_segment_size: 1 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+            manifest_rotation_size: 4 << 20,
+            gc_interval: Some(Duration::from_secs(60)),
         }
     }
 
@@ -103,6 +202,14 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            history_retention: None,
+            lock_timeout: None,
+            conflict_tracking_limit: 1 << 20,
+            txn_spill_threshold: 64 << 20,
+            wal_segment_size: 1 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+            manifest_rotation_size: 4 << 20,
+            gc_interval: Some(Duration::from_secs(60)),
         }
     }
 
@@ -114,6 +221,14 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            history_retention: None,
+            lock_timeout: None,
+            conflict_tracking_limit: 1 << 20,
+            txn_spill_threshold: 64 << 20,
+            wal_segment_size: 1 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+            manifest_rotation_size: 4 << 20,
+            gc_interval: Some(Duration::from_secs(60)),
         }
     }
 }
@@ -149,6 +264,19 @@ fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bo
     table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
 }
 
+/// Get the smallest key that is greater than all keys starting with `prefix`. Returns `None` if there is no such
+/// key, i.e., the prefix is empty or only consists of `0xff`.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < u8::MAX {
+            upper.push(last + 1);
+            return Some(upper);
+        }
+    }
+    None
+}
+
 #[derive(Clone, Debug)]
 pub enum CompactionFilter {
     Prefix(Bytes),
@@ -158,14 +286,23 @@ pub enum CompactionFilter {
 pub(crate) struct LsmStorageInner {
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
     pub(crate) state_lock: Mutex<()>,
-    path: PathBuf,
+    pub(crate) path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
-    next_sst_id: AtomicUsize,
+    pub(crate) next_sst_id: AtomicUsize,
+    /// The smallest SST id each running compaction may write, see `protect_pending_outputs`.
+    pub(crate) pending_outputs: Mutex<Vec<usize>>,
+    /// Held by a running compaction.
+    pub(crate) compaction_lock: Mutex<()>,
+    /// Background compactions are skipped while positive, see `bulk_load`.
+    pub(crate) compaction_pauses: AtomicUsize,
     pub(crate) options: Arc<LsmStorageOptions>,
     pub(crate) compaction_controller: CompactionController,
     pub(crate) manifest: Option<Manifest>,
+    /// The WAL shared by all memtables, if enabled.
+    pub(crate) wal: Option<Wal>,
     pub(crate) mvcc: Option<LsmMvccInner>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    pub(crate) watchers: Watchers,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -257,18 +394,39 @@ impl MiniLsm {
         self.inner.get(key)
     }
 
+    /// Get multiple keys with a single snapshot. Results are returned in input order.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.inner.multi_get(keys)
+    }
+
     pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
         self.inner.write_batch(batch)
     }
 
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        options: WriteOptions,
+    ) -> Result<()> {
+        self.inner.write_batch_with_options(batch, options)
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.inner.put(key, value)
     }
 
+    pub fn put_with_options(&self, key: &[u8], value: &[u8], options: WriteOptions) -> Result<()> {
+        self.inner.put_with_options(key, value, options)
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         self.inner.delete(key)
     }
 
+    pub fn delete_with_options(&self, key: &[u8], options: WriteOptions) -> Result<()> {
+        self.inner.delete_with_options(key, options)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
@@ -277,10 +435,113 @@ impl MiniLsm {
         self.inner.new_txn()
     }
 
+    /// Create a transaction with the given isolation level, instead of the one of `LsmStorageOptions::serializable`.
+    pub fn new_txn_with_options(&self, options: TxnOptions) -> Result<Arc<Transaction>> {
+        self.inner.new_txn_with_options(options)
+    }
+
+    /// Create a read-only snapshot of the current state.
+    pub fn snapshot(&self) -> Snapshot {
+        self.inner.snapshot()
+    }
+
+    /// Create a read-only snapshot as of a historical commit ts. See `LsmStorageOptions::history_retention`.
+    pub fn snapshot_as_of(&self, ts: u64) -> Result<Snapshot> {
+        self.inner.snapshot_as_of(ts)
+    }
+
+    /// Get a key as of a historical commit ts.
+    pub fn get_as_of(&self, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        self.inner.snapshot_as_of(ts)?.get(key)
+    }
+
+    /// Scan a range of keys as of a historical commit ts.
+    pub fn scan_as_of(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<SnapshotIterator> {
+        self.inner.snapshot_as_of(ts)?.scan(lower, upper)
+    }
+
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
         self.inner.scan(lower, upper)
     }
 
+    /// Create an iterator over a range of keys that can be polled for writes committed after its creation.
+    pub fn tailing_scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TailingIterator> {
+        TailingIterator::create(self.inner.clone(), lower, upper)
+    }
+
+    /// Iterate over every version of the keys in the range committed in `(from_ts, to_ts]`, including deletions.
+    /// `from_ts` must not be below `min_readable_ts`, see `ChangeIterator` for the retention guarantees.
+    pub fn changes_between(
+        &self,
+        from_ts: u64,
+        to_ts: u64,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<ChangeIterator> {
+        self.inner.changes_between(from_ts, to_ts, lower, upper)
+    }
+
+    /// Subscribe to the writes to keys with the given prefix, see `Watcher`.
+    pub fn watch(&self, prefix: &[u8]) -> Watcher {
+        self.watch_with_capacity(prefix, DEFAULT_WATCH_CAPACITY)
+    }
+
+    /// Subscribe to the writes to keys with the given prefix, buffering at most `capacity` events.
+    pub fn watch_with_capacity(&self, prefix: &[u8], capacity: usize) -> Watcher {
+        self.inner.watchers.watch(prefix, capacity)
+    }
+
+    /// The earliest ts that can be read with time-travel reads or used as the start of `changes_between`.
+    pub fn min_readable_ts(&self) -> u64 {
+        self.inner.mvcc().min_readable_ts()
+    }
+
+    /// Create an iterator over all keys starting with `prefix`.
+    pub fn prefix_scan(&self, prefix: &[u8]) -> Result<TxnIterator> {
+        self.inner.prefix_scan(prefix)
+    }
+
+    /// What was dropped when the WAL was recovered on open, if the WAL is enabled.
+    pub fn wal_recovery_report(&self) -> Option<WalRecoveryReport> {
+        self.inner
+            .wal
+            .as_ref()
+            .map(|wal| wal.recovery_report().clone())
+    }
+
+    /// Create a consistent copy of the database in the empty or missing directory `path`, which can be opened
+    /// with `MiniLsm::open` while this database keeps serving writes.
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.inner.checkpoint(path.as_ref())?;
+        Ok(())
+    }
+
+    /// Add SSTs written by `SstFileWriter` at a new commit timestamp, which is returned. The files are copied, and
+    /// can be removed afterwards.
+    pub fn ingest(&self, files: &[PathBuf]) -> Result<u64> {
+        self.inner.ingest(files)
+    }
+
+    /// Start loading sorted data directly into the bottom level. Other writes wait until the session is finished or
+    /// dropped.
+    pub fn bulk_load(&self) -> BulkLoad<'_> {
+        self.inner.bulk_load()
+    }
+
+    /// Remove the files in the DB directory that are no longer referenced.
+    pub fn collect_garbage(&self) -> Result<GcReport> {
+        self.inner.collect_garbage(false)
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
         if !self.inner.state.read().memtable.is_empty() {
@@ -337,46 +598,43 @@ impl LsmStorageInner {
         if !path.exists() {
             std::fs::create_dir_all(path).context("failed to create DB dir")?;
         }
-        let manifest_path = path.join("MANIFEST");
         let mut last_commit_ts = 0;
-        if !manifest_path.exists() {
-            if options.enable_wal {
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    state.memtable.id(),
-                    Self::path_of_wal_static(path, state.memtable.id()),
-                )?);
+        let wal;
+        let created;
+        if !Manifest::exists(path) && Manifest::legacy_exists(path) {
+            Self::migrate_legacy_manifest(path, state.clone(), &compaction_controller)
+                .context("failed to migrate legacy manifest")?;
+        }
+        if !Manifest::exists(path) {
+            // the manifest is created before any other file, so that a lost manifest is never mistaken for a new
+            // database, whose garbage collection would remove the SSTs
+            for entry in std::fs::read_dir(path)? {
+                let file = entry?.path();
+                if file
+                    .extension()
+                    .is_some_and(|ext| ext == "sst" || ext == "wal")
+                {
+                    bail!("{} exists without a manifest", file.display());
+                }
             }
-            manifest = Manifest::create(&manifest_path).context("failed to create manifest")?;
-            manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            manifest = Manifest::create(path).context("failed to create manifest")?;
+            created = true;
+            wal = if options.enable_wal {
+                Some(Wal::create(path, options.wal_segment_size)?)
+            } else {
+                None
+            };
         } else {
-            let (m, records) = Manifest::recover(&manifest_path)?;
-            let mut memtables = BTreeSet::new();
-            for record in records {
-                match record {
-                    ManifestRecord::Flush(sst_id) => {
-                        let res = memtables.remove(&sst_id);
-                        assert!(res, "memtable not exist?");
-                        if compaction_controller.flush_to_l0() {
-                            state.l0_sstables.insert(0, sst_id);
-                        } else {
-                            state.levels.insert(0, (sst_id, vec![sst_id]));
-                        }
-                        next_sst_id = next_sst_id.max(sst_id);
-                    }
-                    ManifestRecord::NewMemtable(x) => {
-                        next_sst_id = next_sst_id.max(x);
-                        memtables.insert(x);
-                    }
-                    ManifestRecord::Compaction(task, output) => {
-                        let (new_state, _) = compaction_controller
-                            .apply_compaction_result(&state, &task, &output, true);
-                        // TODO: apply remove again
-                        state = new_state;
-                        next_sst_id =
-                            next_sst_id.max(output.iter().max().copied().unwrap_or_default());
-                    }
-                }
+            let (m, edits) = Manifest::recover(path)?;
+            let mut version = Version::new(std::mem::take(&mut state.levels));
+            for edit in edits {
+                version.apply(edit)?;
             }
+            state.l0_sstables = version.l0_sstables;
+            state.levels = version.levels;
+            let flushed_lsn = version.flushed_lsn;
+            last_commit_ts = version.commit_ts;
+            next_sst_id = version.next_sst_id;
 
             let mut sst_cnt = 0;
             // recover SSTs
@@ -392,57 +650,61 @@ impl LsmStorageInner {
                     FileObject::open(&Self::path_of_sst_static(path, table_id))
                         .context("failed to open SST")?,
                 )?;
+                if sst.table_size() != version.files[&table_id].size {
+                    bail!(
+                        "{}.sst has size {}, but the manifest records {}",
+                        table_id,
+                        sst.table_size(),
+                        version.files[&table_id].size
+                    );
+                }
                 last_commit_ts = last_commit_ts.max(sst.max_ts());
                 state.sstables.insert(table_id, Arc::new(sst));
                 sst_cnt += 1;
             }
             println!("{} SSTs opened", sst_cnt);
 
-            next_sst_id += 1;
-
-            // Sort SSTs on each level (only for leveled compaction)
-            if let CompactionController::Leveled(_) = &compaction_controller {
-                for (_id, ssts) in &mut state.levels {
-                    ssts.sort_by(|x, y| {
-                        state
-                            .sstables
-                            .get(x)
-                            .unwrap()
-                            .first_key()
-                            .cmp(state.sstables.get(y).unwrap().first_key())
-                    })
-                }
-            }
-
             // recover memtables
             if options.enable_wal {
-                let mut wal_cnt = 0;
-                for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
-                        .max()
-                        .unwrap_or_default();
-                    last_commit_ts = last_commit_ts.max(max_ts);
-                    if !memtable.is_empty() {
-                        state.imm_memtables.insert(0, Arc::new(memtable));
-                        wal_cnt += 1;
+                let (w, entries) = Wal::recover(
+                    path,
+                    options.wal_segment_size,
+                    flushed_lsn,
+                    options.wal_recovery_mode,
+                )?;
+                if !entries.is_empty() {
+                    let memtable = MemTable::create(next_sst_id);
+                    next_sst_id += 1;
+                    for entry in &entries {
+                        let data = entry
+                            .records
+                            .iter()
+                            .map(|(key, value)| (key.as_key_slice(), &value[..]))
+                            .collect::<Vec<_>>();
+                        memtable.put_batch(&data)?;
+                        memtable.set_max_lsn(entry.lsn);
+                        for (key, _) in &entry.records {
+                            last_commit_ts = last_commit_ts.max(key.ts());
+                        }
                     }
+                    state.imm_memtables.insert(0, Arc::new(memtable));
                 }
-                println!("{} WALs recovered", wal_cnt);
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    next_sst_id,
-                    Self::path_of_wal_static(path, next_sst_id),
-                )?);
+                let report = w.recovery_report();
+                println!(
+                    "{} WAL entries recovered, {} corrupted ranges ({} bytes) and {} entries dropped",
+                    report.replayed_entries,
+                    report.corruptions.len(),
+                    report.dropped_bytes(),
+                    report.dropped_entries
+                );
+                wal = Some(w);
             } else {
-                state.memtable = Arc::new(MemTable::create(next_sst_id));
+                wal = None;
             }
-            m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            state.memtable = Arc::new(MemTable::create(next_sst_id));
             next_sst_id += 1;
             manifest = m;
+            created = false;
         };
 
         let storage = Self {
@@ -451,29 +713,114 @@ impl LsmStorageInner {
             path: path.to_path_buf(),
             block_cache,
             next_sst_id: AtomicUsize::new(next_sst_id),
+            pending_outputs: Mutex::new(Vec::new()),
+            compaction_lock: Mutex::new(()),
+            compaction_pauses: AtomicUsize::new(0),
             compaction_controller,
             manifest: Some(manifest),
+            wal,
+            mvcc: Some(LsmMvccInner::new(last_commit_ts, options.history_retention)),
             options: options.into(),
-            mvcc: Some(LsmMvccInner::new(last_commit_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            watchers: Watchers::default(),
         };
         storage.sync_dir()?;
+        storage.maybe_rotate_manifest(&storage.state_lock.lock())?;
+        // a new directory has nothing to collect, and must not lose files it was not expected to have
+        if !created {
+            storage.collect_garbage(true)?;
+        }
 
         Ok(storage)
     }
 
+    /// Replace a manifest written before the manifest was rotated with a snapshot of the state it records.
+    fn migrate_legacy_manifest(
+        path: &Path,
+        mut state: LsmStorageState,
+        compaction_controller: &CompactionController,
+    ) -> Result<()> {
+        let mut next_sst_id = 0;
+        let mut flushed_lsn = 0;
+        for record in Manifest::read_legacy(path)? {
+            match record {
+                LegacyManifestRecord::Flush(sst_id, lsn) => {
+                    flushed_lsn = flushed_lsn.max(lsn);
+                    if compaction_controller.flush_to_l0() {
+                        state.l0_sstables.insert(0, sst_id);
+                    } else {
+                        state.levels.insert(0, (sst_id, vec![sst_id]));
+                    }
+                    next_sst_id = next_sst_id.max(sst_id);
+                }
+                LegacyManifestRecord::Compaction(task, output) => {
+                    let (new_state, _) =
+                        compaction_controller.apply_compaction_result(&state, &task, &output, true);
+                    state = new_state;
+                    next_sst_id = next_sst_id.max(output.iter().max().copied().unwrap_or_default());
+                }
+            }
+        }
+        // the legacy manifest does not record the commit ts and the metadata of the SSTs
+        let mut commit_ts = 0;
+        let table_ids = state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, files)| files))
+            .copied()
+            .collect::<Vec<_>>();
+        for table_id in table_ids {
+            let sst = SsTable::open(
+                table_id,
+                None,
+                FileObject::open(&Self::path_of_sst_static(path, table_id))
+                    .context("failed to open SST")?,
+            )?;
+            commit_ts = commit_ts.max(sst.max_ts());
+            state.sstables.insert(table_id, Arc::new(sst));
+        }
+        let snapshot = VersionEdit::snapshot(&state, next_sst_id + 1, commit_ts, flushed_lsn);
+        Manifest::migrate_legacy(path, snapshot)
+    }
+
+    /// Start a new manifest with a snapshot of the current state, so that the records before it are no longer
+    /// replayed on open.
+    pub(crate) fn rotate_manifest(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
+        let snapshot = VersionEdit::snapshot(
+            &self.state.read(),
+            self.next_sst_id.load(std::sync::atomic::Ordering::SeqCst),
+            self.mvcc().latest_commit_ts(),
+            self.wal.as_ref().map_or(0, |wal| wal.flushed_lsn()),
+        );
+        self.manifest().rotate(state_lock_observer, snapshot)
+    }
+
+    /// Rotate the manifest once it grows beyond `manifest_rotation_size`.
+    pub(crate) fn maybe_rotate_manifest(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+    ) -> Result<()> {
+        if self.manifest().size() > self.options.manifest_rotation_size as u64 {
+            self.rotate_manifest(state_lock_observer)?;
+        }
+        Ok(())
+    }
+
     pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
         let mut compaction_filters = self.compaction_filters.lock();
         compaction_filters.push(compaction_filter);
     }
 
     pub fn sync(&self) -> Result<()> {
-        self.state.read().memtable.sync_wal()
+        if let Some(wal) = &self.wal {
+            wal.sync()?;
+        }
+        Ok(())
     }
 
     /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
     pub fn get(self: &Arc<Self>, key: &[u8]) -> Result<Option<Bytes>> {
-        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        let txn = self.mvcc().new_txn(self.clone(), self.txn_options(true));
         txn.get(key)
     }
 
@@ -556,49 +903,325 @@ impl LsmStorageInner {
         Ok(None)
     }
 
-    pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
+    /// Get multiple keys from the same snapshot. Keys are looked up in sorted order, so that each bloom filter and
+    /// decoded block is only consulted once for all keys that fall into it. Results are returned in input order.
+    pub(crate) fn multi_get_with_ts(
+        &self,
+        keys: &[&[u8]],
+        read_ts: u64,
+    ) -> Result<Vec<Option<Bytes>>> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        }; // drop global lock here
+
+        let mut order = (0..keys.len()).collect::<Vec<_>>();
+        order.sort_by_key(|idx| keys[*idx]);
+        let sorted_keys = order
+            .iter()
+            .map(|idx| (keys[*idx], farmhash::fingerprint32(keys[*idx])))
+            .collect::<Vec<_>>();
+        // The newest version found for each sorted key. Sources are visited from the latest to the earliest, so the
+        // first version found is the one to return.
+        let mut found: Vec<Option<Bytes>> = vec![None; keys.len()];
+
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            for (idx, (key, _)) in sorted_keys.iter().enumerate() {
+                if found[idx].is_some() {
+                    continue;
+                }
+                let iter = memtable.scan(
+                    Bound::Included(KeySlice::from_slice(key, read_ts)),
+                    Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
+                );
+                if iter.is_valid() {
+                    found[idx] = Some(Bytes::copy_from_slice(iter.value()));
+                }
+            }
+        }
+
+        let sst_ids = snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flat_map(|(_, files)| files));
+        for sst_id in sst_ids {
+            let unresolved = (0..sorted_keys.len())
+                .filter(|idx| found[*idx].is_none())
+                .collect::<Vec<_>>();
+            if unresolved.is_empty() {
+                break;
+            }
+            let batch = unresolved
+                .iter()
+                .map(|idx| sorted_keys[*idx])
+                .collect::<Vec<_>>();
+            let values = snapshot.sstables[sst_id].multi_get_with_ts(&batch, read_ts)?;
+            for (idx, value) in unresolved.into_iter().zip(values) {
+                found[idx] = value;
+            }
+        }
+
+        let mut result = vec![None; keys.len()];
+        for (idx, value) in order.into_iter().zip(found) {
+            result[idx] = value.filter(|value| !value.is_empty());
+        }
+        Ok(result)
+    }
+
+    /// Get multiple keys from the storage with a single snapshot. Results are returned in input order.
+    pub fn multi_get(self: &Arc<Self>, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        let txn = self.mvcc().new_txn(self.clone(), self.txn_options(true));
+        txn.multi_get(keys)
+    }
+
+    /// Write records at a single commit ts, which becomes visible after all of them are written. Records are
+    /// consumed one at a time and all go to the same memtable. With the WAL enabled, they are appended in chunks of
+    /// `TXN_CHUNK_SIZE` bytes between a begin and a commit entry, so that they are recovered all-or-nothing without
+    /// encoding the whole batch at once.
+    pub(crate) fn write_records_inner<T: AsRef<[u8]>>(
+        &self,
+        records: impl Iterator<Item = Result<WriteBatchRecord<T>>>,
+        options: WriteOptions,
+    ) -> Result<u64> {
+        if options.sync && options.disable_wal {
+            bail!("cannot sync a write without WAL");
+        }
         let _lck = self.mvcc().write_lock.lock();
         let ts = self.mvcc().latest_commit_ts() + 1;
-        for record in batch {
-            match record {
-                WriteBatchRecord::Del(key) => {
-                    let key = key.as_ref();
+        let mut watched = Vec::new();
+        let mut wal_batch = WalBatch::default();
+        let wal = self.wal.as_ref().filter(|_| !options.disable_wal);
+        let memtable;
+        {
+            // the memtable cannot be frozen while the state is read locked
+            let guard = self.state.read();
+            memtable = guard.memtable.clone();
+            let write = || -> Result<()> {
+                if self.wal.is_some() && wal.is_none() {
+                    memtable.set_unlogged();
+                }
+                let mut began = false;
+                for record in records {
+                    let record = record?;
+                    let (key, value) = match &record {
+                        WriteBatchRecord::Put(key, value) => {
+                            let value = value.as_ref();
+                            assert!(!value.is_empty(), "value cannot be empty");
+                            (key.as_ref(), value)
+                        }
+                        WriteBatchRecord::Del(key) => (key.as_ref(), &b""[..]),
+                    };
                     assert!(!key.is_empty(), "key cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), b"")?;
-                        size = guard.memtable.approximate_size();
+                    let key = KeySlice::from_slice(key, ts);
+                    memtable.put(key, value)?;
+                    if let Some(wal) = wal {
+                        wal_batch.put(key, value);
+                        if wal_batch.len() >= TXN_CHUNK_SIZE {
+                            let kind = if began {
+                                WalEntryKind::TxnPart
+                            } else {
+                                WalEntryKind::TxnBegin
+                            };
+                            memtable.set_max_lsn(wal.append_entry(&wal_batch, kind)?);
+                            wal_batch = WalBatch::default();
+                            began = true;
+                        }
+                    }
+                    if self.watchers.is_watched(key.key_ref()) {
+                        watched.push(record);
                     }
-                    self.try_freeze(size)?;
                 }
-                WriteBatchRecord::Put(key, value) => {
-                    let key = key.as_ref();
-                    let value = value.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
-                    assert!(!value.is_empty(), "value cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), value)?;
-                        size = guard.memtable.approximate_size();
+                if let Some(wal) = wal {
+                    if began {
+                        memtable
+                            .set_max_lsn(wal.append_entry(&wal_batch, WalEntryKind::TxnCommit)?);
+                    } else if !wal_batch.is_empty() {
+                        memtable.set_max_lsn(wal.append(&wal_batch)?);
                     }
-                    self.try_freeze(size)?;
                 }
+                Ok(())
+            };
+            if let Err(e) = write() {
+                // the records put so far are removed before `ts` becomes visible, and `ts` is not reused, as the WAL
+                // may hold some chunks of the batch, which are dropped on recovery without a commit entry
+                memtable.remove_ts(ts);
+                self.mvcc().update_commit_ts(ts);
+                return Err(e);
             }
         }
         self.mvcc().update_commit_ts(ts);
+        self.watchers.notify(&watched, ts);
+        self.freeze_after_commit(memtable.approximate_size());
+        drop(_lck);
+        if options.sync {
+            self.sync()?;
+        }
         Ok(ts)
     }
 
+    /// Write a batch outside of a transaction.
+    fn write_batch_direct<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        options: WriteOptions,
+    ) -> Result<()> {
+        let batch = batch
+            .iter()
+            .map(|record| match record {
+                WriteBatchRecord::Put(key, value) => WriteBatchRecord::Put(
+                    Bytes::copy_from_slice(key.as_ref()),
+                    Bytes::copy_from_slice(value.as_ref()),
+                ),
+                WriteBatchRecord::Del(key) => {
+                    WriteBatchRecord::Del(Bytes::copy_from_slice(key.as_ref()))
+                }
+            })
+            .collect();
+        self.commit(CommitRequest::new(batch, options, None, None))?;
+        Ok(())
+    }
+
+    /// Commit a request together with the requests of concurrent writers, returning its commit ts.
+    pub(crate) fn commit(&self, request: CommitRequest) -> Result<u64> {
+        if request.options.sync && request.options.disable_wal {
+            bail!("cannot sync a write without WAL");
+        }
+        // checked before joining a group, so that an invalid request cannot fail the leader
+        for record in &request.batch {
+            if record.key().is_empty() {
+                bail!("key cannot be empty");
+            }
+            if matches!(record, WriteBatchRecord::Put(_, value) if value.is_empty()) {
+                bail!("value cannot be empty");
+            }
+        }
+        let request = Arc::new(request);
+        let commit_queue = &self.mvcc().commit_queue;
+        if let Some(group) = commit_queue.join(&request) {
+            self.commit_group(&group);
+            commit_queue.finish(group.len());
+        }
+        request.take_result()
+    }
+
+    /// Commit a group of requests as the leader. Each request is assigned the next commit ts, all of them are written
+    /// to the memtable and one WAL record, and become visible at once. The WAL is synced once after the write and
+    /// commit locks are released, so that the next group can be written meanwhile.
+    fn commit_group(&self, group: &[Arc<CommitRequest>]) {
+        let mvcc = self.mvcc();
+        let commit_lock = mvcc.commit_lock.lock();
+        let write_lock = mvcc.write_lock.lock();
+        let initial_ts = mvcc.latest_commit_ts();
+        let mut ts = initial_ts;
+        let mut committed = Vec::with_capacity(group.len());
+        for request in group {
+            if let Some(conflict_set) = &request.conflict_set {
+                if let Err(e) = mvcc.check_conflicts(request.read_ts.unwrap(), conflict_set) {
+                    request.set_result(Err(e));
+                    continue;
+                }
+            }
+            ts += 1;
+            // the write set must be recorded before later requests of the group are checked
+            let write_set = match &request.conflict_set {
+                // a transaction that wrote nothing cannot be a conflict of a later one
+                Some(_) if request.batch.is_empty() => None,
+                Some(conflict_set) => Some(conflict_set.write_set.clone()),
+                None if !request.batch.is_empty() && mvcc.has_serializable_txns() => {
+                    Some(KeySet::from_keys(
+                        request.batch.iter().map(|record| record.key()),
+                        self.options.conflict_tracking_limit,
+                    ))
+                }
+                None => None,
+            };
+            if let Some(write_set) = write_set {
+                mvcc.record_commit(ts, request.read_ts.unwrap_or(ts - 1), write_set);
+            }
+            committed.push((request, ts));
+        }
+
+        let mut data = Vec::new();
+        let mut wal_batch = WalBatch::default();
+        let mut unlogged = false;
+        for (request, ts) in &committed {
+            let write_wal = self.wal.is_some() && !request.options.disable_wal;
+            unlogged |= self.wal.is_some() && !write_wal;
+            for record in &request.batch {
+                let (key, value) = match record {
+                    WriteBatchRecord::Put(key, value) => (key, &value[..]),
+                    WriteBatchRecord::Del(key) => (key, &b""[..]),
+                };
+                let key = KeySlice::from_slice(key, *ts);
+                if write_wal {
+                    wal_batch.put(key, value);
+                }
+                data.push((key, value));
+            }
+        }
+        let write = || -> Result<usize> {
+            // the memtable cannot be frozen while the state is read locked, so that it receives WAL entries in LSN
+            // order
+            let guard = self.state.read();
+            if let Some(wal) = &self.wal {
+                if !wal_batch.is_empty() {
+                    guard.memtable.set_max_lsn(wal.append(&wal_batch)?);
+                }
+            }
+            if unlogged {
+                guard.memtable.set_unlogged();
+            }
+            guard.memtable.put_batch(&data)?;
+            Ok(guard.memtable.approximate_size())
+        };
+        let mut result = write().map(|size| {
+            mvcc.update_commit_ts(ts);
+            for (request, ts) in &committed {
+                self.watchers.notify(&request.batch, *ts);
+            }
+            self.freeze_after_commit(size);
+        });
+        if result.is_err() {
+            // nothing was put into the memtable, but the WAL may hold the group, so its timestamps are not reused
+            mvcc.remove_commits_after(initial_ts);
+            mvcc.update_commit_ts(ts);
+        }
+        drop(write_lock);
+        drop(commit_lock);
+
+        if result.is_ok() && committed.iter().any(|(request, _)| request.options.sync) {
+            result = self.sync();
+        }
+        match result {
+            Ok(_) => {
+                for (request, ts) in committed {
+                    request.set_result(Ok(ts));
+                }
+            }
+            Err(e) => {
+                for (request, _) in committed {
+                    request.set_result(Err(anyhow!("group commit failed: {:#}", e)));
+                }
+            }
+        }
+    }
+
     pub fn write_batch<T: AsRef<[u8]>>(
         self: &Arc<Self>,
         batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        self.write_batch_with_options(batch, WriteOptions::default())
+    }
+
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[WriteBatchRecord<T>],
+        options: WriteOptions,
     ) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(batch)?;
+            self.write_batch_direct(batch, options)?;
         } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            let txn = self.mvcc().new_txn(self.clone(), self.txn_options(false));
             for record in batch {
                 match record {
                     WriteBatchRecord::Del(key) => {
@@ -609,35 +1232,56 @@ impl LsmStorageInner {
                     }
                 }
             }
-            txn.commit()?;
+            txn.commit_with_options(options)?;
         }
         Ok(())
     }
 
     /// Put a key-value pair into the storage by writing into the current memtable.
     pub fn put(self: &Arc<Self>, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_with_options(key, value, WriteOptions::default())
+    }
+
+    pub fn put_with_options(
+        self: &Arc<Self>,
+        key: &[u8],
+        value: &[u8],
+        options: WriteOptions,
+    ) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Put(key, value)])?;
+            self.write_batch_direct(&[WriteBatchRecord::Put(key, value)], options)?;
         } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            let txn = self.mvcc().new_txn(self.clone(), self.txn_options(false));
             txn.put(key, value);
-            txn.commit()?;
+            txn.commit_with_options(options)?;
         }
         Ok(())
     }
 
     /// Remove a key from the storage by writing an empty value.
     pub fn delete(self: &Arc<Self>, key: &[u8]) -> Result<()> {
+        self.delete_with_options(key, WriteOptions::default())
+    }
+
+    pub fn delete_with_options(self: &Arc<Self>, key: &[u8], options: WriteOptions) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Del(key)])?;
+            self.write_batch_direct(&[WriteBatchRecord::Del(key)], options)?;
         } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            let txn = self.mvcc().new_txn(self.clone(), self.txn_options(false));
             txn.delete(key);
-            txn.commit()?;
+            txn.commit_with_options(options)?;
         }
         Ok(())
     }
 
+    /// Freeze the memtable after a write is committed. A failure does not fail the write, and the freeze is retried
+    /// by the next write, as the memtable is still too large.
+    fn freeze_after_commit(&self, estimated_size: usize) {
+        if let Err(e) = self.try_freeze(estimated_size) {
+            eprintln!("failed to freeze the memtable: {}", e);
+        }
+    }
+
     fn try_freeze(&self, estimated_size: usize) -> Result<()> {
         if estimated_size >= self.options.target_sst_size {
             let state_lock = self.state_lock.lock();
@@ -659,12 +1303,8 @@ impl LsmStorageInner {
         Self::path_of_sst_static(&self.path, id)
     }
 
-    pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
-        path.as_ref().join(format!("{:05}.wal", id))
-    }
-
-    pub(crate) fn path_of_wal(&self, id: usize) -> PathBuf {
-        Self::path_of_wal_static(&self.path, id)
+    pub(crate) fn path_of_spill(&self, id: usize) -> PathBuf {
+        self.path.join(format!("{:05}.spill", id))
     }
 
     pub(super) fn sync_dir(&self) -> Result<()> {
@@ -682,33 +1322,14 @@ impl LsmStorageInner {
         // Update the snapshot.
         *guard = Arc::new(snapshot);
 
-        drop(guard);
-        old_memtable.sync_wal()?;
-
         Ok(())
     }
 
-    /// Force freeze the current memtable to an immutable memtable
-    pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
+    /// Force freeze the current memtable to an immutable memtable. The WAL is shared by all memtables, so this does
+    /// no I/O.
+    pub fn force_freeze_memtable(&self, _state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
         let memtable_id = self.next_sst_id();
-        let memtable = if self.options.enable_wal {
-            Arc::new(MemTable::create_with_wal(
-                memtable_id,
-                self.path_of_wal(memtable_id),
-            )?)
-        } else {
-            Arc::new(MemTable::create(memtable_id))
-        };
-
-        self.freeze_memtable_with_memtable(memtable)?;
-
-        self.manifest().add_record(
-            state_lock_observer,
-            ManifestRecord::NewMemtable(memtable_id),
-        )?;
-        self.sync_dir()?;
-
-        Ok(())
+        self.freeze_memtable_with_memtable(Arc::new(MemTable::create(memtable_id)))
     }
 
     /// Force flush the earliest-created immutable memtable to disk
@@ -736,6 +1357,7 @@ impl LsmStorageInner {
         )?);
 
         // Add the flushed L0 table to the list.
+        let mut edit;
         {
             let mut guard = self.state.write();
             let mut snapshot = guard.as_ref().clone();
@@ -752,24 +1374,66 @@ impl LsmStorageInner {
             }
             println!("flushed {}.sst with size={}", sst_id, sst.table_size());
             snapshot.sstables.insert(sst_id, sst);
+            edit = VersionEdit::diff(&guard, &snapshot);
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
 
-        if self.options.enable_wal {
-            std::fs::remove_file(self.path_of_wal(sst_id))?;
-        }
+        self.sync_dir()?;
 
-        self.manifest()
-            .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
+        let flushed_lsn = flush_memtable.max_lsn();
+        edit.flushed_lsn = Some(flushed_lsn);
+        self.manifest().add_record(&state_lock, edit)?;
 
-        self.sync_dir()?;
+        if let Some(wal) = &self.wal {
+            wal.release(flushed_lsn)?;
+        }
+        self.maybe_rotate_manifest(&state_lock)?;
 
         Ok(())
     }
 
     pub fn new_txn(self: &Arc<Self>) -> Result<Arc<Transaction>> {
-        Ok(self.mvcc().new_txn(self.clone(), self.options.serializable))
+        Ok(self.mvcc().new_txn(self.clone(), self.txn_options(false)))
+    }
+
+    pub fn new_txn_with_options(self: &Arc<Self>, options: TxnOptions) -> Result<Arc<Transaction>> {
+        Ok(self.mvcc().new_txn(self.clone(), options))
+    }
+
+    /// The options of transactions that do not specify them, following `LsmStorageOptions::serializable`. Single
+    /// reads outside of a transaction are read-only, so they never track conflicts.
+    fn txn_options(&self, read_only: bool) -> TxnOptions {
+        TxnOptions {
+            isolation: if self.options.serializable {
+                IsolationLevel::Serializable
+            } else {
+                IsolationLevel::Snapshot
+            },
+            read_only,
+        }
+    }
+
+    pub fn snapshot(self: &Arc<Self>) -> Snapshot {
+        self.mvcc().new_snapshot(self.clone())
+    }
+
+    pub fn snapshot_as_of(self: &Arc<Self>, ts: u64) -> Result<Snapshot> {
+        self.mvcc().new_snapshot_as_of(self.clone(), ts)
+    }
+
+    pub fn changes_between(
+        self: &Arc<Self>,
+        from_ts: u64,
+        to_ts: u64,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<ChangeIterator> {
+        // pin `from_ts` so that compaction does not collapse the versions in the window
+        let snapshot = self.snapshot_as_of(from_ts)?;
+        let to_ts = to_ts.min(self.mvcc().latest_commit_ts());
+        let iter = self.scan_all_versions(lower, upper)?;
+        ChangeIterator::new(iter, map_bound(upper), to_ts, snapshot)
     }
 
     /// Create an iterator over a range of keys.
@@ -778,29 +1442,67 @@ impl LsmStorageInner {
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
     ) -> Result<TxnIterator> {
-        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        let txn = self.mvcc().new_txn(self.clone(), self.txn_options(true));
         txn.scan(lower, upper)
     }
 
+    /// Create an iterator over all keys starting with `prefix`.
+    pub fn prefix_scan(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_txn(self.clone(), self.txn_options(true));
+        txn.prefix_scan(prefix)
+    }
+
     pub(crate) fn scan_with_ts(
         &self,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
         read_ts: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
+        let iter = self.scan_all_versions(lower, upper)?;
+        Ok(FusedIterator::new(LsmIterator::new(
+            iter,
+            map_bound(upper),
+            read_ts,
+        )?))
+    }
+
+    /// Create an iterator over all versions of the keys in the range, newest version first for each key. The
+    /// iterator may go beyond `upper`, and the caller should check the end bound.
+    pub(crate) fn scan_all_versions(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<LsmIteratorInner> {
+        self.scan_versions_newer_than(lower, upper, None)
+    }
+
+    /// Like `scan_all_versions`, but skip the SSTs whose versions are all at or below `min_ts`, if given. The
+    /// memtables are always scanned.
+    pub(crate) fn scan_versions_newer_than(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        min_ts: Option<u64>,
+    ) -> Result<LsmIteratorInner> {
+        let is_newer = |table: &SsTable| min_ts.is_none_or(|ts| table.max_ts() > ts);
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
         }; // drop global lock here
 
+        // an excluded lower bound skips all versions of the key
+        let memtable_lower = match lower {
+            Bound::Excluded(key) => Bound::Excluded(KeySlice::from_slice(key, key::TS_RANGE_END)),
+            lower => map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
+        };
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(
-            map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
+            memtable_lower,
             map_key_bound_plus_ts(upper, key::TS_RANGE_END),
         )));
         for memtable in snapshot.imm_memtables.iter() {
             memtable_iters.push(Box::new(memtable.scan(
-                map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
+                memtable_lower,
                 map_key_bound_plus_ts(upper, key::TS_RANGE_END),
             )));
         }
@@ -809,12 +1511,14 @@ impl LsmStorageInner {
         let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
         for table_id in snapshot.l0_sstables.iter() {
             let table = snapshot.sstables[table_id].clone();
-            if range_overlap(
-                lower,
-                upper,
-                table.first_key().as_key_slice(),
-                table.last_key().as_key_slice(),
-            ) {
+            if is_newer(&table)
+                && range_overlap(
+                    lower,
+                    upper,
+                    table.first_key().as_key_slice(),
+                    table.last_key().as_key_slice(),
+                )
+            {
                 let iter = match lower {
                     Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
                         table,
@@ -833,7 +1537,7 @@ impl LsmStorageInner {
                     Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table)?,
                 };
 
-                table_iters.push(Box::new(iter));
+                table_iters.push(Box::new(iter.with_upper_bound(upper)));
             }
         }
 
@@ -843,12 +1547,14 @@ impl LsmStorageInner {
             let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
             for table in level_sst_ids {
                 let table = snapshot.sstables[table].clone();
-                if range_overlap(
-                    lower,
-                    upper,
-                    table.first_key().as_key_slice(),
-                    table.last_key().as_key_slice(),
-                ) {
+                if is_newer(&table)
+                    && range_overlap(
+                        lower,
+                        upper,
+                        table.first_key().as_key_slice(),
+                        table.last_key().as_key_slice(),
+                    )
+                {
                     level_ssts.push(table);
                 }
             }
@@ -870,16 +1576,10 @@ impl LsmStorageInner {
                 }
                 Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level_ssts)?,
             };
-            level_iters.push(Box::new(level_iter));
+            level_iters.push(Box::new(level_iter.with_upper_bound(upper)?));
         }
 
         let iter = TwoMergeIterator::create(memtable_iter, l0_iter)?;
-        let iter = TwoMergeIterator::create(iter, MergeIterator::create(level_iters))?;
-
-        Ok(FusedIterator::new(LsmIterator::new(
-            iter,
-            map_bound(upper),
-            read_ts,
-        )?))
+        TwoMergeIterator::create(iter, MergeIterator::create(level_iters))
     }
 }
@@ -1,85 +1,640 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
 use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 
 use crate::compact::CompactionTask;
+use crate::key::KeyBytes;
+use crate::lsm_storage::LsmStorageState;
+use crate::table::SsTable;
 
+/// Every manifest file starts with the magic number and the format version.
+const MANIFEST_MAGIC: &[u8; 4] = b"MLSM";
+const FORMAT_VERSION: u16 = 1;
+
+/// A version edit is encoded as a sequence of fields, each of which is a tag (u8), the length of the value (u32) and
+/// the value. Readers skip unknown fields with this bit set in the tag, and fail on other unknown fields.
+const TAG_IGNORABLE: u8 = 0x80;
+const TAG_LEVEL_IDS: u8 = 1;
+const TAG_DELETED_FILE: u8 = 2;
+const TAG_NEW_FILE: u8 = 3;
+const TAG_NEXT_SST_ID: u8 = 4;
+const TAG_COMMIT_TS: u8 = 5;
+const TAG_FLUSHED_LSN: u8 = 6;
+
+/// The manifest records how the SSTs are organized, as a sequence of version edits. Edits are appended to the
+/// current manifest file, named `MANIFEST-<id>`, which is pointed to by the `CURRENT` file. Once it grows too
+/// large, the manifest is rotated: a new file starting with a snapshot of the whole state replaces it, so that the
+/// old edits are no longer replayed.
 pub struct Manifest {
-    file: Arc<Mutex<File>>,
+    dir: PathBuf,
+    inner: Arc<Mutex<ManifestFile>>,
 }
 
+struct ManifestFile {
+    file: File,
+    id: usize,
+    size: u64,
+}
+
+/// The name of a manifest written before the manifest was rotated, which is migrated on open.
+const LEGACY_MANIFEST: &str = "MANIFEST";
+
+/// A record of a legacy manifest, encoded as JSON.
 #[derive(Serialize, Deserialize)]
-pub enum ManifestRecord {
-    Flush(usize),
-    NewMemtable(usize),
+pub enum LegacyManifestRecord {
+    /// A memtable is flushed to the SST of the same id. WAL entries up to the LSN are persisted in SSTs.
+    Flush(usize, u64),
     Compaction(CompactionTask, Vec<usize>),
 }
 
+/// A level in a version edit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LevelId {
+    L0,
+    /// A level of leveled compaction, or a tier of tiered compaction.
+    Level(usize),
+}
+
+/// The metadata of an SST recorded in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMeta {
+    pub id: usize,
+    pub first_key: KeyBytes,
+    pub last_key: KeyBytes,
+    pub size: u64,
+    pub min_ts: u64,
+    pub max_ts: u64,
+}
+
+impl FileMeta {
+    pub fn from_sst(sst: &SsTable) -> Self {
+        Self {
+            id: sst.sst_id(),
+            first_key: sst.first_key().clone(),
+            last_key: sst.last_key().clone(),
+            size: sst.table_size(),
+            min_ts: sst.min_ts(),
+            max_ts: sst.max_ts(),
+        }
+    }
+}
+
+/// A change to the SSTs of the LSM tree. Edits are applied by removing the deleted files, then reordering the
+/// levels if `level_ids` is set, then adding the new files.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// The ids of all levels after the edit, in order, if it adds or removes levels. Only tiered compaction does.
+    pub level_ids: Option<Vec<usize>>,
+    pub deleted_files: Vec<(LevelId, usize)>,
+    /// New files of L0 are listed from latest to earliest, and put before the existing ones.
+    pub new_files: Vec<(LevelId, FileMeta)>,
+    /// The next SST id to be allocated.
+    pub next_sst_id: Option<usize>,
+    /// The latest commit ts, which may no longer be found in the SSTs.
+    pub commit_ts: Option<u64>,
+    /// WAL entries up to this LSN are persisted in SSTs.
+    pub flushed_lsn: Option<u64>,
+}
+
+impl VersionEdit {
+    /// The edit turning the SSTs of `old` into the SSTs of `new`.
+    pub fn diff(old: &LsmStorageState, new: &LsmStorageState) -> Self {
+        let mut edit = Self::default();
+        let old_l0 = old.l0_sstables.iter().collect::<HashSet<_>>();
+        let new_l0 = new.l0_sstables.iter().collect::<HashSet<_>>();
+        for id in &old.l0_sstables {
+            if !new_l0.contains(id) {
+                edit.deleted_files.push((LevelId::L0, *id));
+            }
+        }
+        for id in &new.l0_sstables {
+            if !old_l0.contains(id) {
+                edit.new_files
+                    .push((LevelId::L0, FileMeta::from_sst(&new.sstables[id])));
+            }
+        }
+        let old_levels = old
+            .levels
+            .iter()
+            .map(|(level, files)| (*level, files.iter().collect::<HashSet<_>>()))
+            .collect::<HashMap<_, _>>();
+        let new_levels = new
+            .levels
+            .iter()
+            .map(|(level, files)| (*level, files.iter().collect::<HashSet<_>>()))
+            .collect::<HashMap<_, _>>();
+        for (level, files) in &old.levels {
+            for id in files {
+                if !new_levels
+                    .get(level)
+                    .is_some_and(|files| files.contains(id))
+                {
+                    edit.deleted_files.push((LevelId::Level(*level), *id));
+                }
+            }
+        }
+        for (level, files) in &new.levels {
+            for id in files {
+                if !old_levels
+                    .get(level)
+                    .is_some_and(|files| files.contains(id))
+                {
+                    edit.new_files.push((
+                        LevelId::Level(*level),
+                        FileMeta::from_sst(&new.sstables[id]),
+                    ));
+                }
+            }
+        }
+        let level_ids = |state: &LsmStorageState| {
+            state
+                .levels
+                .iter()
+                .map(|(level, _)| *level)
+                .collect::<Vec<_>>()
+        };
+        if level_ids(old) != level_ids(new) {
+            edit.level_ids = Some(level_ids(new));
+        }
+        edit
+    }
+
+    /// The edit creating the SSTs of `state` from scratch, which starts a rotated manifest.
+    pub fn snapshot(
+        state: &LsmStorageState,
+        next_sst_id: usize,
+        commit_ts: u64,
+        flushed_lsn: u64,
+    ) -> Self {
+        let mut new_files = Vec::new();
+        for id in &state.l0_sstables {
+            new_files.push((LevelId::L0, FileMeta::from_sst(&state.sstables[id])));
+        }
+        for (level, files) in &state.levels {
+            for id in files {
+                new_files.push((
+                    LevelId::Level(*level),
+                    FileMeta::from_sst(&state.sstables[id]),
+                ));
+            }
+        }
+        Self {
+            level_ids: Some(state.levels.iter().map(|(level, _)| *level).collect()),
+            deleted_files: Vec::new(),
+            new_files,
+            next_sst_id: Some(next_sst_id),
+            commit_ts: Some(commit_ts),
+            flushed_lsn: Some(flushed_lsn),
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        fn put_field(buf: &mut Vec<u8>, tag: u8, value: &[u8]) {
+            buf.put_u8(tag);
+            buf.put_u32(value.len() as u32);
+            buf.put_slice(value);
+        }
+        fn put_level(buf: &mut Vec<u8>, level: LevelId) {
+            match level {
+                LevelId::L0 => buf.put_u8(0),
+                LevelId::Level(id) => {
+                    buf.put_u8(1);
+                    buf.put_u64(id as u64);
+                }
+            }
+        }
+        fn put_key(buf: &mut Vec<u8>, key: &KeyBytes) {
+            buf.put_u16(key.key_len() as u16);
+            buf.put_slice(key.key_ref());
+            buf.put_u64(key.ts());
+        }
+        let mut value = Vec::new();
+        if let Some(level_ids) = &self.level_ids {
+            value.put_u32(level_ids.len() as u32);
+            for id in level_ids {
+                value.put_u64(*id as u64);
+            }
+            put_field(buf, TAG_LEVEL_IDS, &value);
+        }
+        for (level, id) in &self.deleted_files {
+            value.clear();
+            put_level(&mut value, *level);
+            value.put_u64(*id as u64);
+            put_field(buf, TAG_DELETED_FILE, &value);
+        }
+        for (level, file) in &self.new_files {
+            value.clear();
+            put_level(&mut value, *level);
+            value.put_u64(file.id as u64);
+            value.put_u64(file.size);
+            value.put_u64(file.min_ts);
+            value.put_u64(file.max_ts);
+            put_key(&mut value, &file.first_key);
+            put_key(&mut value, &file.last_key);
+            put_field(buf, TAG_NEW_FILE, &value);
+        }
+        for (tag, field) in [
+            (TAG_NEXT_SST_ID, self.next_sst_id.map(|id| id as u64)),
+            (TAG_COMMIT_TS, self.commit_ts),
+            (TAG_FLUSHED_LSN, self.flushed_lsn),
+        ] {
+            if let Some(field) = field {
+                put_field(buf, tag, &field.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+            if buf.len() < len {
+                bail!("manifest record is too short");
+            }
+            let (data, rest) = buf.split_at(len);
+            *buf = rest;
+            Ok(data)
+        }
+        fn get_u64(buf: &mut &[u8]) -> Result<u64> {
+            Ok(take(buf, 8)?.get_u64())
+        }
+        fn get_level(buf: &mut &[u8]) -> Result<LevelId> {
+            match take(buf, 1)?[0] {
+                0 => Ok(LevelId::L0),
+                1 => Ok(LevelId::Level(get_u64(buf)? as usize)),
+                kind => bail!("unknown level kind {}", kind),
+            }
+        }
+        fn get_key(buf: &mut &[u8]) -> Result<KeyBytes> {
+            let len = take(buf, 2)?.get_u16() as usize;
+            let key = Bytes::copy_from_slice(take(buf, len)?);
+            Ok(KeyBytes::from_bytes_with_ts(key, get_u64(buf)?))
+        }
+
+        let mut edit = Self::default();
+        while !buf.is_empty() {
+            let tag = take(&mut buf, 1)?[0];
+            let len = take(&mut buf, 4)?.get_u32() as usize;
+            let mut value = take(&mut buf, len)?;
+            match tag {
+                TAG_LEVEL_IDS => {
+                    let num = take(&mut value, 4)?.get_u32();
+                    let mut level_ids = Vec::new();
+                    for _ in 0..num {
+                        level_ids.push(get_u64(&mut value)? as usize);
+                    }
+                    edit.level_ids = Some(level_ids);
+                }
+                TAG_DELETED_FILE => {
+                    let level = get_level(&mut value)?;
+                    edit.deleted_files
+                        .push((level, get_u64(&mut value)? as usize));
+                }
+                TAG_NEW_FILE => {
+                    let level = get_level(&mut value)?;
+                    let file = FileMeta {
+                        id: get_u64(&mut value)? as usize,
+                        size: get_u64(&mut value)?,
+                        min_ts: get_u64(&mut value)?,
+                        max_ts: get_u64(&mut value)?,
+                        first_key: get_key(&mut value)?,
+                        last_key: get_key(&mut value)?,
+                    };
+                    edit.new_files.push((level, file));
+                }
+                TAG_NEXT_SST_ID => edit.next_sst_id = Some(get_u64(&mut value)? as usize),
+                TAG_COMMIT_TS => edit.commit_ts = Some(get_u64(&mut value)?),
+                TAG_FLUSHED_LSN => edit.flushed_lsn = Some(get_u64(&mut value)?),
+                tag if tag & TAG_IGNORABLE != 0 => {}
+                tag => bail!("unknown manifest field {}", tag),
+            }
+        }
+        Ok(edit)
+    }
+}
+
+/// The SSTs of the LSM tree, rebuilt by replaying version edits.
+pub struct Version {
+    pub l0_sstables: Vec<usize>,
+    pub levels: Vec<(usize, Vec<usize>)>,
+    pub files: HashMap<usize, FileMeta>,
+    /// The smallest SST id not used by any edit.
+    pub next_sst_id: usize,
+    pub commit_ts: u64,
+    pub flushed_lsn: u64,
+}
+
+impl Version {
+    /// Start from a tree without SSTs, with the initial levels of the compaction strategy.
+    pub fn new(levels: Vec<(usize, Vec<usize>)>) -> Self {
+        Self {
+            l0_sstables: Vec::new(),
+            levels,
+            files: HashMap::new(),
+            next_sst_id: 1,
+            commit_ts: 0,
+            flushed_lsn: 0,
+        }
+    }
+
+    fn level_mut(&mut self, level: LevelId) -> Result<&mut Vec<usize>> {
+        match level {
+            LevelId::L0 => Ok(&mut self.l0_sstables),
+            LevelId::Level(id) => match self.levels.iter_mut().find(|(level, _)| *level == id) {
+                Some((_, files)) => Ok(files),
+                None => bail!("level {} not found", id),
+            },
+        }
+    }
+
+    pub fn apply(&mut self, edit: VersionEdit) -> Result<()> {
+        for (level, id) in edit.deleted_files {
+            let files = self.level_mut(level)?;
+            let Some(idx) = files.iter().position(|x| *x == id) else {
+                bail!("SST {} not found in {:?}", id, level);
+            };
+            files.remove(idx);
+            self.files.remove(&id);
+        }
+        if let Some(level_ids) = edit.level_ids {
+            let mut levels = std::mem::take(&mut self.levels)
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+            self.levels = level_ids
+                .into_iter()
+                .map(|id| (id, levels.remove(&id).unwrap_or_default()))
+                .collect();
+            if levels.values().any(|files| !files.is_empty()) {
+                bail!("removed levels are not empty");
+            }
+        }
+        let mut new_l0 = Vec::new();
+        let mut changed_levels = HashSet::new();
+        for (level, file) in edit.new_files {
+            self.next_sst_id = self.next_sst_id.max(file.id + 1);
+            match level {
+                LevelId::L0 => new_l0.push(file.id),
+                LevelId::Level(_) => {
+                    self.level_mut(level)?.push(file.id);
+                    changed_levels.insert(level);
+                }
+            }
+            self.files.insert(file.id, file);
+        }
+        if !new_l0.is_empty() {
+            new_l0.append(&mut self.l0_sstables);
+            self.l0_sstables = new_l0;
+        }
+        // SSTs in all other levels are sorted by key range
+        for level in changed_levels {
+            let mut level_files = std::mem::take(self.level_mut(level)?);
+            level_files.sort_by(|x, y| self.files[x].first_key.cmp(&self.files[y].first_key));
+            *self.level_mut(level)? = level_files;
+        }
+        if let Some(next_sst_id) = edit.next_sst_id {
+            self.next_sst_id = self.next_sst_id.max(next_sst_id);
+        }
+        if let Some(commit_ts) = edit.commit_ts {
+            self.commit_ts = self.commit_ts.max(commit_ts);
+        }
+        if let Some(flushed_lsn) = edit.flushed_lsn {
+            self.flushed_lsn = self.flushed_lsn.max(flushed_lsn);
+        }
+        Ok(())
+    }
+}
+
+fn manifest_name(id: usize) -> String {
+    format!("MANIFEST-{:06}", id)
+}
+
+fn manifest_header() -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.put_slice(MANIFEST_MAGIC);
+    buf.put_u16(FORMAT_VERSION);
+    buf
+}
+
+fn encode_record(edit: &VersionEdit) -> Vec<u8> {
+    let mut body = Vec::new();
+    edit.encode(&mut body);
+    let mut buf = Vec::with_capacity(body.len() + 8);
+    buf.put_u32(body.len() as u32);
+    buf.put_slice(&body);
+    buf.put_u32(crc32fast::hash(&body));
+    buf
+}
+
+/// Point the `CURRENT` file to the manifest `id`. The new content is written to a temporary file and renamed, so
+/// that `CURRENT` always points to a complete manifest.
+fn set_current(dir: &Path, id: usize) -> Result<()> {
+    let tmp_path = dir.join("CURRENT.tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(format!("{}\n", manifest_name(id)).as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, dir.join("CURRENT"))?;
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
 impl Manifest {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+    /// Whether a manifest has been created in `dir`.
+    pub fn exists(dir: impl AsRef<Path>) -> bool {
+        dir.as_ref().join("CURRENT").exists()
+    }
+
+    /// Whether `dir` has a legacy manifest to be migrated, see `read_legacy`.
+    pub fn legacy_exists(dir: impl AsRef<Path>) -> bool {
+        dir.as_ref().join(LEGACY_MANIFEST).exists()
+    }
+
+    /// Read the records of the legacy manifest in `dir`, each of which is the length of the JSON (u64), the JSON
+    /// and its checksum (u32).
+    pub fn read_legacy(dir: impl AsRef<Path>) -> Result<Vec<LegacyManifestRecord>> {
+        let buf = std::fs::read(dir.as_ref().join(LEGACY_MANIFEST))
+            .context("failed to read legacy manifest")?;
+        let mut buf_ptr = buf.as_slice();
+        let mut records = Vec::new();
+        while buf_ptr.has_remaining() {
+            if buf_ptr.remaining() < 8 {
+                bail!("incomplete legacy manifest record");
+            }
+            let len = buf_ptr.get_u64() as usize;
+            if buf_ptr.remaining() < len + 4 {
+                bail!("incomplete legacy manifest record");
+            }
+            let slice = &buf_ptr[..len];
+            buf_ptr.advance(len);
+            let checksum = buf_ptr.get_u32();
+            if checksum != crc32fast::hash(slice) {
+                bail!("checksum mismatched!");
+            }
+            records
+                .push(serde_json::from_slice(slice).context("unsupported legacy manifest record")?);
+        }
+        Ok(records)
+    }
+
+    /// Replace the legacy manifest in `dir` with a manifest only containing `snapshot`.
+    pub fn migrate_legacy(dir: impl AsRef<Path>, snapshot: VersionEdit) -> Result<()> {
+        let dir = dir.as_ref();
+        // left behind by a migration that crashed before writing `CURRENT`
+        let partial = dir.join(manifest_name(1));
+        if partial.exists() {
+            std::fs::remove_file(partial)?;
+        }
+        Self::write_snapshot(dir, snapshot)?;
+        std::fs::remove_file(dir.join(LEGACY_MANIFEST))?;
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create_new(true)
+            .write(true)
+            .open(dir.join(manifest_name(1)))
+            .context("failed to create manifest")?;
+        let header = manifest_header();
+        file.write_all(&header)?;
+        file.sync_all()?;
+        set_current(dir, 1)?;
         Ok(Self {
-            file: Arc::new(Mutex::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create manifest")?,
-            )),
+            dir: dir.to_path_buf(),
+            inner: Arc::new(Mutex::new(ManifestFile {
+                file,
+                id: 1,
+                size: header.len() as u64,
+            })),
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+    /// Write a manifest only containing `snapshot` to `dir`, for a copy of the database.
+    pub fn write_snapshot(dir: impl AsRef<Path>, snapshot: VersionEdit) -> Result<()> {
+        let dir = dir.as_ref();
+        let mut buf = manifest_header();
+        buf.extend(encode_record(&snapshot));
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(dir.join(manifest_name(1)))
+            .context("failed to create manifest")?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        set_current(dir, 1)
+    }
+
+    pub fn recover(dir: impl AsRef<Path>) -> Result<(Self, Vec<VersionEdit>)> {
+        let dir = dir.as_ref();
+        let current = std::fs::read_to_string(dir.join("CURRENT"))?;
+        let id = match current.trim_end().strip_prefix("MANIFEST-") {
+            Some(id) => id.parse().context("invalid CURRENT file")?,
+            None => bail!("invalid CURRENT file: {:?}", current),
+        };
         let mut file = OpenOptions::new()
             .read(true)
             .append(true)
-            .open(path)
+            .open(dir.join(manifest_name(id)))
             .context("failed to recover manifest")?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        let mut buf_ptr = buf.as_slice();
-        let mut records = Vec::new();
+        let header = manifest_header();
+        if buf.len() < header.len() || buf[..MANIFEST_MAGIC.len()] != MANIFEST_MAGIC[..] {
+            bail!("not a manifest file");
+        }
+        let version = (&buf[MANIFEST_MAGIC.len()..header.len()]).get_u16();
+        if version > FORMAT_VERSION {
+            bail!("unsupported manifest format version {}", version);
+        }
+        let mut buf_ptr = &buf[header.len()..];
+        let mut edits = Vec::new();
         while buf_ptr.has_remaining() {
-            let len = buf_ptr.get_u64();
-            let slice = &buf_ptr[..len as usize];
-            let json = serde_json::from_slice::<ManifestRecord>(slice)?;
-            buf_ptr.advance(len as usize);
+            if buf_ptr.remaining() < 4 {
+                bail!("incomplete manifest record");
+            }
+            let len = buf_ptr.get_u32() as usize;
+            if buf_ptr.remaining() < len + 4 {
+                bail!("incomplete manifest record");
+            }
+            let slice = &buf_ptr[..len];
+            buf_ptr.advance(len);
             let checksum = buf_ptr.get_u32();
             if checksum != crc32fast::hash(slice) {
                 bail!("checksum mismatched!");
             }
-            records.push(json);
+            edits.push(VersionEdit::decode(slice)?);
         }
         Ok((
             Self {
-                file: Arc::new(Mutex::new(file)),
+                dir: dir.to_path_buf(),
+                inner: Arc::new(Mutex::new(ManifestFile {
+                    file,
+                    id,
+                    size: buf.len() as u64,
+                })),
             },
-            records,
+            edits,
         ))
     }
 
     pub fn add_record(
         &self,
         _state_lock_observer: &MutexGuard<()>,
-        record: ManifestRecord,
+        edit: VersionEdit,
     ) -> Result<()> {
-        self.add_record_when_init(record)
+        let mut inner = self.inner.lock();
+        let buf = encode_record(&edit);
+        inner.file.write_all(&buf)?;
+        inner.file.sync_all()?;
+        inner.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// The name of the current manifest file.
+    pub fn file_name(&self) -> String {
+        manifest_name(self.inner.lock().id)
+    }
+
+    /// The size of the current manifest file in bytes.
+    pub fn size(&self) -> u64 {
+        self.inner.lock().size
     }
 
-    pub fn add_record_when_init(&self, record: ManifestRecord) -> Result<()> {
-        let mut file = self.file.lock();
-        let mut buf = serde_json::to_vec(&record)?;
-        let hash = crc32fast::hash(&buf);
-        file.write_all(&(buf.len() as u64).to_be_bytes())?;
-        buf.put_u32(hash);
+    /// Switch to a new manifest file starting with `snapshot`, and remove the old one.
+    pub fn rotate(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        snapshot: VersionEdit,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock();
+        let id = inner.id + 1;
+        let mut buf = manifest_header();
+        buf.extend(encode_record(&snapshot));
+        // a file left behind by a rotation that crashed before switching `CURRENT` is overwritten
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(self.dir.join(manifest_name(id)))
+            .context("failed to create manifest")?;
         file.write_all(&buf)?;
         file.sync_all()?;
+        set_current(&self.dir, id)?;
+        let old_id = inner.id;
+        *inner = ManifestFile {
+            file,
+            id,
+            size: buf.len() as u64,
+        };
+        std::fs::remove_file(self.dir.join(manifest_name(old_id)))?;
         Ok(())
     }
 }
@@ -1,6 +1,5 @@
 use std::ops::Bound;
-use std::path::Path;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -12,7 +11,6 @@ use ouroboros::self_referencing;
 use crate::iterators::StorageIterator;
 use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
@@ -20,9 +18,12 @@ use crate::wal::Wal;
 /// chapters of week 1 and week 2.
 pub struct MemTable {
     pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
-    wal: Option<Wal>,
     id: usize,
     approximate_size: Arc<AtomicUsize>,
+    /// The LSN of the last WAL entry put into the mem-table, 0 if none.
+    max_lsn: AtomicU64,
+    /// Whether a write skipping the WAL was put into the mem-table, which the WAL cannot recover.
+    unlogged: AtomicBool,
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -64,32 +65,12 @@ impl MemTable {
         Self {
             id,
             map: Arc::new(SkipMap::new()),
-            wal: None,
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            max_lsn: AtomicU64::new(0),
+            unlogged: AtomicBool::new(false),
         }
     }
 
-    /// Create a new mem-table with WAL
-    pub fn create_with_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self {
-            id,
-            map: Arc::new(SkipMap::new()),
-            wal: Some(Wal::create(path.as_ref())?),
-            approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
-    }
-
-    /// Create a memtable from WAL
-    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        let map = Arc::new(SkipMap::new());
-        Ok(Self {
-            id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
-            map,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
-    }
-
     /// Get a value by key. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
         let key_bytes = KeyBytes::from_bytes_with_ts(
@@ -139,17 +120,36 @@ impl MemTable {
         }
         self.approximate_size
             .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
-        if let Some(ref wal) = self.wal {
-            wal.put_batch(data)?;
-        }
         Ok(())
     }
 
-    pub fn sync_wal(&self) -> Result<()> {
-        if let Some(ref wal) = self.wal {
-            wal.sync()?;
+    /// Remove the entries of a write that failed at `ts`, which must not be visible to any reader yet. This scans
+    /// the whole mem-table, and is only used on the error path.
+    pub(crate) fn remove_ts(&self, ts: u64) {
+        for entry in self.map.iter() {
+            if entry.key().ts() == ts {
+                entry.remove();
+            }
         }
-        Ok(())
+    }
+
+    /// Record that the WAL entry `lsn` was put into the mem-table. The WAL can be recycled up to the largest such
+    /// LSN once the mem-table is flushed.
+    pub fn set_max_lsn(&self, lsn: u64) {
+        self.max_lsn.fetch_max(lsn, Ordering::SeqCst);
+    }
+
+    pub fn max_lsn(&self) -> u64 {
+        self.max_lsn.load(Ordering::SeqCst)
+    }
+
+    /// Record that a write skipping the WAL is put into the mem-table.
+    pub fn set_unlogged(&self) {
+        self.unlogged.store(true, Ordering::SeqCst);
+    }
+
+    pub fn has_unlogged(&self) -> bool {
+        self.unlogged.load(Ordering::SeqCst)
     }
 
     /// Get an iterator over a range of keys.
@@ -157,8 +157,9 @@ impl MemTable {
         let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
+            iter_builder: |map| map.range((lower, upper.clone())),
             item: (KeyBytes::new(), Bytes::new()),
+            upper: upper.clone(),
         }
         .build();
         iter.next().unwrap();
@@ -210,6 +211,8 @@ pub struct MemTableIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (KeyBytes, Bytes),
+    /// Stores the upper bound of the range, so that the iterator can be re-seeked.
+    upper: Bound<KeyBytes>,
 }
 
 impl MemTableIterator {
@@ -240,4 +243,13 @@ impl StorageIterator for MemTableIterator {
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let lower = Bound::Included(KeyBytes::from_bytes_with_ts(
+            Bytes::copy_from_slice(key.key_ref()),
+            key.ts(),
+        ));
+        self.with_mut(|x| *x.iter = x.map.range((lower, x.upper.clone())));
+        self.next()
+    }
 }
@@ -1,23 +1,42 @@
 #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+pub mod changes;
+pub(crate) mod commit_queue;
+pub(crate) mod conflict;
+pub mod lock_table;
+pub mod snapshot;
+pub mod spill;
+pub mod tailing;
 pub mod txn;
 pub mod watermark;
 
 use std::{
-    collections::{BTreeMap, HashSet},
-    sync::{atomic::AtomicBool, Arc},
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
-use crossbeam_skiplist::SkipMap;
-use parking_lot::Mutex;
+use anyhow::{bail, Result};
+use parking_lot::{Mutex, RwLock};
 
-use crate::lsm_storage::LsmStorageInner;
+use crate::lsm_storage::{HistoryRetention, LsmStorageInner};
 
-use self::{txn::Transaction, watermark::Watermark};
+use self::{
+    commit_queue::CommitQueue,
+    conflict::{ConflictSet, KeySet},
+    lock_table::LockTable,
+    snapshot::Snapshot,
+    spill::LocalStorage,
+    txn::{IsolationLevel, Transaction, TxnOptions},
+    watermark::Watermark,
+};
 
 pub(crate) struct CommittedTxnData {
-    pub(crate) key_hashes: HashSet<u32>,
+    pub(crate) write_set: KeySet,
     #[allow(dead_code)]
     pub(crate) read_ts: u64,
     #[allow(dead_code)]
@@ -27,17 +46,43 @@ pub(crate) struct CommittedTxnData {
 pub(crate) struct LsmMvccInner {
     pub(crate) write_lock: Mutex<()>,
     pub(crate) commit_lock: Mutex<()>,
+    pub(crate) commit_queue: CommitQueue,
     pub(crate) ts: Arc<Mutex<(u64, Watermark)>>,
     pub(crate) committed_txns: Arc<Mutex<BTreeMap<u64, CommittedTxnData>>>,
+    pub(crate) retention: Option<HistoryRetention>,
+    /// The time each commit ts became visible, only tracked for time-based history retention.
+    commit_times: Mutex<BTreeMap<u64, Instant>>,
+    /// Compaction may have garbage collected versions that are only visible to read ts below this ts.
+    gc_ts: AtomicU64,
+    pub(crate) lock_table: LockTable,
+    next_txn_id: AtomicU64,
+    /// Commits only record their write sets while there are serializable transactions to check them.
+    pub(crate) active_serializable_txns: AtomicUsize,
 }
 
 impl LsmMvccInner {
-    pub fn new(initial_ts: u64) -> Self {
+    pub fn new(initial_ts: u64, retention: Option<HistoryRetention>) -> Self {
+        // History retained before a restart cannot be trusted beyond what the retention policy guarantees.
+        let gc_ts = match retention {
+            Some(HistoryRetention::Commits(k)) => initial_ts.saturating_sub(k),
+            _ => initial_ts,
+        };
+        let mut commit_times = BTreeMap::new();
+        if let Some(HistoryRetention::Duration(_)) = retention {
+            commit_times.insert(initial_ts, Instant::now());
+        }
         Self {
             write_lock: Mutex::new(()),
             commit_lock: Mutex::new(()),
+            commit_queue: CommitQueue::default(),
             ts: Arc::new(Mutex::new((initial_ts, Watermark::new()))),
             committed_txns: Arc::new(Mutex::new(BTreeMap::new())),
+            retention,
+            commit_times: Mutex::new(commit_times),
+            gc_ts: AtomicU64::new(gc_ts),
+            lock_table: LockTable::new(),
+            next_txn_id: AtomicU64::new(0),
+            active_serializable_txns: AtomicUsize::new(0),
         }
     }
 
@@ -46,29 +91,177 @@ impl LsmMvccInner {
     }
 
     pub fn update_commit_ts(&self, ts: u64) {
+        if let Some(HistoryRetention::Duration(duration)) = self.retention {
+            let now = Instant::now();
+            let mut commit_times = self.commit_times.lock();
+            commit_times.insert(ts, now);
+            // Only keep the latest commit before the retention window, which is the state at the window start.
+            if let Some(cutoff) = now.checked_sub(duration) {
+                while commit_times.len() > 1 {
+                    let mut iter = commit_times.values();
+                    let (first, second) = (iter.next().unwrap(), iter.next().unwrap());
+                    if *first <= cutoff && *second <= cutoff {
+                        commit_times.pop_first();
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
         self.ts.lock().0 = ts;
     }
 
+    /// The earliest read ts that must stay readable according to the history retention policy.
+    fn retention_ts(&self, latest_commit_ts: u64) -> Option<u64> {
+        match self.retention {
+            None => None,
+            Some(HistoryRetention::Commits(k)) => Some(latest_commit_ts.saturating_sub(k)),
+            Some(HistoryRetention::Duration(duration)) => {
+                let commit_times = self.commit_times.lock();
+                let retention_ts = match Instant::now().checked_sub(duration) {
+                    Some(cutoff) => commit_times
+                        .iter()
+                        .rev()
+                        .find(|(_, time)| **time <= cutoff)
+                        .map(|(ts, _)| *ts),
+                    None => None,
+                };
+                retention_ts.or_else(|| commit_times.keys().next().copied())
+            }
+        }
+    }
+
+    /// All ts (strictly) below this ts can be garbage collected by compaction. Compared with `watermark`, this also
+    /// honors the history retention policy. The returned ts is recorded so that time-travel reads below it are
+    /// rejected.
+    pub fn gc_watermark(&self) -> u64 {
+        let ts = self.ts.lock();
+        let mut watermark = ts.1.watermark().unwrap_or(ts.0);
+        if let Some(retention_ts) = self.retention_ts(ts.0) {
+            watermark = watermark.min(retention_ts);
+        }
+        self.gc_ts.fetch_max(watermark, Ordering::SeqCst);
+        watermark
+    }
+
+    /// The earliest ts that can be read with time-travel reads.
+    pub fn min_readable_ts(&self) -> u64 {
+        self.gc_ts.load(Ordering::SeqCst)
+    }
+
     /// All ts (strictly) below this ts can be garbage collected.
     pub fn watermark(&self) -> u64 {
         let ts = self.ts.lock();
         ts.1.watermark().unwrap_or(ts.0)
     }
 
-    pub fn new_txn(&self, inner: Arc<LsmStorageInner>, serializable: bool) -> Arc<Transaction> {
+    pub fn new_txn(&self, inner: Arc<LsmStorageInner>, options: TxnOptions) -> Arc<Transaction> {
         let mut ts = self.ts.lock();
         let read_ts = ts.0;
         ts.1.add_reader(read_ts);
+        let serializable = options.isolation == IsolationLevel::Serializable && !options.read_only;
+        if serializable {
+            // under the ts lock, so that any commit after `read_ts` sees the counter
+            self.active_serializable_txns.fetch_add(1, Ordering::SeqCst);
+        }
+        let lock_timeout = inner.options.lock_timeout;
+        let conflict_tracking_limit = inner.options.conflict_tracking_limit;
         Arc::new(Transaction {
             inner,
             read_ts,
-            local_storage: Arc::new(SkipMap::new()),
+            isolation: options.isolation,
+            read_only: options.read_only,
+            local_storage: RwLock::new(LocalStorage::default()),
             committed: Arc::new(AtomicBool::new(false)),
-            key_hashes: if serializable {
-                Some(Mutex::new((HashSet::new(), HashSet::new())))
+            conflict_set: if options.isolation == IsolationLevel::Serializable && !options.read_only
+            {
+                Some(Mutex::new(ConflictSet::new(conflict_tracking_limit)))
             } else {
                 None
             },
+            counted_serializable: AtomicBool::new(serializable),
+            savepoints: Mutex::new(Vec::new()),
+            txn_id: self.next_txn_id.fetch_add(1, Ordering::SeqCst),
+            lock_timeout,
+            locked_keys: Mutex::new(Vec::new()),
+            write_error: Mutex::new(None),
         })
     }
+
+    pub(crate) fn has_serializable_txns(&self) -> bool {
+        self.active_serializable_txns.load(Ordering::SeqCst) > 0
+    }
+
+    /// Record the keys written by a commit for the conflict checks of serializable transactions. The caller must
+    /// hold the commit lock.
+    pub(crate) fn record_commit(&self, commit_ts: u64, read_ts: u64, write_set: KeySet) {
+        let mut committed_txns = self.committed_txns.lock();
+        let old_data = committed_txns.insert(
+            commit_ts,
+            CommittedTxnData {
+                write_set,
+                read_ts,
+                commit_ts,
+            },
+        );
+        assert!(old_data.is_none());
+
+        // remove unneeded txn data
+        let watermark = self.watermark();
+        while let Some(entry) = committed_txns.first_entry() {
+            if *entry.key() < watermark {
+                entry.remove();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Forget the commits recorded after `ts`, whose write failed, so that they do not fail the conflict checks of
+    /// others. The caller must hold the commit lock.
+    pub(crate) fn remove_commits_after(&self, ts: u64) {
+        self.committed_txns.lock().split_off(&(ts + 1));
+    }
+
+    /// Fail if anything a serializable transaction read was written by a commit after its `read_ts`. The caller
+    /// must hold the commit lock.
+    pub(crate) fn check_conflicts(&self, read_ts: u64, conflict_set: &ConflictSet) -> Result<()> {
+        if conflict_set.write_set.is_empty() {
+            return Ok(());
+        }
+        let committed_txns = self.committed_txns.lock();
+        for (_, txn_data) in committed_txns.range((read_ts + 1)..) {
+            if conflict_set.conflicts_with(&txn_data.write_set) {
+                bail!("serializable check failed");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn new_snapshot(&self, inner: Arc<LsmStorageInner>) -> Snapshot {
+        let mut ts = self.ts.lock();
+        let read_ts = ts.0;
+        ts.1.add_reader(read_ts);
+        Snapshot::new(inner, read_ts)
+    }
+
+    /// Create a snapshot at a historical ts. Fails if the versions at that ts may have been garbage collected.
+    pub fn new_snapshot_as_of(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        read_ts: u64,
+    ) -> Result<Snapshot> {
+        let mut ts = self.ts.lock();
+        let read_ts = read_ts.min(ts.0);
+        let min_readable_ts = self.min_readable_ts();
+        if read_ts < min_readable_ts {
+            bail!(
+                "ts {} is not retained, the earliest readable ts is {}",
+                read_ts,
+                min_readable_ts
+            );
+        }
+        ts.1.add_reader(read_ts);
+        Ok(Snapshot::new(inner, read_ts))
+    }
 }
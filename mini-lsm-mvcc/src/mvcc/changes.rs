@@ -0,0 +1,114 @@
+use std::ops::Bound;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    iterators::StorageIterator,
+    key::{self, KeySlice},
+    lsm_iterator::LsmIteratorInner,
+};
+
+use super::snapshot::Snapshot;
+
+/// An iterator over every version committed in `(from_ts, to_ts]`, in key order and newest version first for
+/// each key. Deletions are yielded as tombstones with an empty value.
+///
+/// The iterator pins `from_ts` in the watermark, so compaction keeps every version committed after `from_ts`
+/// until the iterator is dropped. Versions older than `MiniLsm::min_readable_ts` may already have been
+/// collapsed, so `from_ts` must not be below it. Use `LsmStorageOptions::history_retention` to keep a window
+/// of changes available to consumers that are not always connected.
+pub struct ChangeIterator {
+    inner: LsmIteratorInner,
+    end_bound: Bound<Bytes>,
+    from_ts: u64,
+    to_ts: u64,
+    is_valid: bool,
+    _snapshot: Snapshot,
+}
+
+impl ChangeIterator {
+    pub(crate) fn new(
+        inner: LsmIteratorInner,
+        end_bound: Bound<Bytes>,
+        to_ts: u64,
+        snapshot: Snapshot,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            inner,
+            end_bound,
+            from_ts: snapshot.read_ts(),
+            to_ts,
+            is_valid: false,
+            _snapshot: snapshot,
+        };
+        iter.move_to_change()?;
+        Ok(iter)
+    }
+
+    /// Skip versions outside of the ts window, stopping at the end bound.
+    fn move_to_change(&mut self) -> Result<()> {
+        self.is_valid = false;
+        while self.inner.is_valid() {
+            let within_end_bound = match self.end_bound.as_ref() {
+                Bound::Unbounded => true,
+                Bound::Included(key) => self.inner.key().key_ref() <= key.as_ref(),
+                Bound::Excluded(key) => self.inner.key().key_ref() < key.as_ref(),
+            };
+            if !within_end_bound {
+                break;
+            }
+            let ts = self.inner.key().ts();
+            if ts > self.from_ts && ts <= self.to_ts {
+                self.is_valid = true;
+                break;
+            }
+            self.inner.next()?;
+        }
+        Ok(())
+    }
+
+    /// The commit ts of the current version.
+    pub fn commit_ts(&self) -> u64 {
+        self.inner.key().ts()
+    }
+
+    /// Whether the current version is a deletion.
+    pub fn is_tombstone(&self) -> bool {
+        self.inner.value().is_empty()
+    }
+}
+
+impl StorageIterator for ChangeIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn is_valid(&self) -> bool {
+        self.is_valid
+    }
+
+    fn key(&self) -> &[u8] {
+        self.inner.key().key_ref()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if !self.is_valid {
+            return Ok(());
+        }
+        self.inner.next()?;
+        self.move_to_change()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.inner
+            .seek(KeySlice::from_slice(key, key::TS_RANGE_BEGIN))?;
+        self.move_to_change()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+
+use crate::lsm_storage::{WriteBatchRecord, WriteOptions};
+
+use super::conflict::ConflictSet;
+
+/// A group never grows beyond this many bytes.
+const MAX_GROUP_SIZE: usize = 1 << 20;
+/// A small first batch only takes this many more bytes, so that small writes are not slowed down too much.
+const SMALL_GROUP_SLACK: usize = 128 << 10;
+
+/// A write batch or transaction waiting to be committed.
+pub(crate) struct CommitRequest {
+    pub(crate) batch: Vec<WriteBatchRecord<Bytes>>,
+    pub(crate) options: WriteOptions,
+    /// The read ts of a transaction. Writes outside of transactions are based on the state right before them.
+    pub(crate) read_ts: Option<u64>,
+    /// The read and write sets of a serializable transaction, checked before the request is committed.
+    pub(crate) conflict_set: Option<ConflictSet>,
+    size: usize,
+    /// The commit ts once committed, or why the commit failed.
+    result: Mutex<Option<Result<u64>>>,
+}
+
+impl CommitRequest {
+    pub(crate) fn new(
+        batch: Vec<WriteBatchRecord<Bytes>>,
+        options: WriteOptions,
+        read_ts: Option<u64>,
+        conflict_set: Option<ConflictSet>,
+    ) -> Self {
+        let size = batch
+            .iter()
+            .map(|record| match record {
+                WriteBatchRecord::Put(key, value) => key.len() + value.len(),
+                WriteBatchRecord::Del(key) => key.len(),
+            })
+            .sum();
+        Self {
+            batch,
+            options,
+            read_ts,
+            conflict_set,
+            size,
+            result: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn set_result(&self, result: Result<u64>) {
+        *self.result.lock() = Some(result);
+    }
+
+    pub(crate) fn take_result(&self) -> Result<u64> {
+        self.result
+            .lock()
+            .take()
+            .expect("commit request is not processed")
+    }
+
+    fn is_done(&self) -> bool {
+        self.result.lock().is_some()
+    }
+}
+
+/// Leader/follower group commit. Writers queue up their requests, the writer at the front of the queue becomes the
+/// leader and commits the requests behind it as one group, with one WAL record and at most one fsync, while the
+/// others wait for it.
+#[derive(Default)]
+pub(crate) struct CommitQueue {
+    queue: Mutex<VecDeque<Arc<CommitRequest>>>,
+    processed: Condvar,
+}
+
+impl CommitQueue {
+    /// Queue a request and wait until it is committed by a leader, returning `None`, or until it reaches the front
+    /// of the queue, returning the group this writer has to commit as the leader. The leader must set the results
+    /// of the whole group and call `finish`.
+    pub(crate) fn join(&self, request: &Arc<CommitRequest>) -> Option<Vec<Arc<CommitRequest>>> {
+        let mut queue = self.queue.lock();
+        queue.push_back(request.clone());
+        loop {
+            if request.is_done() {
+                return None;
+            }
+            if Arc::ptr_eq(queue.front().unwrap(), request) {
+                let max_size = if request.size <= SMALL_GROUP_SLACK {
+                    request.size + SMALL_GROUP_SLACK
+                } else {
+                    MAX_GROUP_SIZE
+                };
+                let mut size = 0;
+                let mut group = Vec::new();
+                for request in queue.iter() {
+                    if !group.is_empty() && size + request.size > max_size {
+                        break;
+                    }
+                    size += request.size;
+                    group.push(request.clone());
+                }
+                return Some(group);
+            }
+            self.processed.wait(&mut queue);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn for_testing_num_queued(&self) -> usize {
+        self.queue.lock().len()
+    }
+
+    /// Remove a committed group from the queue, and wake up its followers and the next leader.
+    pub(crate) fn finish(&self, group_len: usize) {
+        let mut queue = self.queue.lock();
+        queue.drain(..group_len);
+        self.processed.notify_all();
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::HashSet,
+    mem,
+    ops::{Bound, RangeBounds},
+};
+
+use bytes::Bytes;
+
+/// A set of keys, either exact or as hashes once it grows too large.
+#[derive(Debug, Clone)]
+pub(crate) enum KeySet {
+    Exact(HashSet<Bytes>),
+    /// Hash collisions may cause false-positive conflicts.
+    Hashed(HashSet<u32>),
+}
+
+impl Default for KeySet {
+    fn default() -> Self {
+        Self::Exact(HashSet::new())
+    }
+}
+
+impl KeySet {
+    /// Insert a key, returning the number of bytes newly tracked.
+    fn insert(&mut self, key: &[u8]) -> usize {
+        match self {
+            Self::Exact(keys) => {
+                if keys.contains(key) {
+                    0
+                } else {
+                    keys.insert(Bytes::copy_from_slice(key));
+                    key.len()
+                }
+            }
+            Self::Hashed(hashes) => {
+                hashes.insert(farmhash::hash32(key));
+                0
+            }
+        }
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        match self {
+            Self::Exact(keys) => keys.contains(key),
+            Self::Hashed(hashes) => hashes.contains(&farmhash::hash32(key)),
+        }
+    }
+
+    /// Collect the keys written by a commit, falling back to hashes above `limit` bytes.
+    pub(crate) fn from_keys<'a>(keys: impl Iterator<Item = &'a [u8]>, limit: usize) -> Self {
+        let mut conflict_set = ConflictSet::new(limit);
+        for key in keys {
+            conflict_set.add_write(key);
+        }
+        conflict_set.take_write_set()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            Self::Exact(keys) => keys.is_empty(),
+            Self::Hashed(hashes) => hashes.is_empty(),
+        }
+    }
+
+    fn to_hashed(&self) -> Self {
+        match self {
+            Self::Exact(keys) => {
+                Self::Hashed(keys.iter().map(|key| farmhash::hash32(key)).collect())
+            }
+            Self::Hashed(hashes) => Self::Hashed(hashes.clone()),
+        }
+    }
+}
+
+/// The read and write sets of a serializable transaction. Reads record exact keys and scans record the key range
+/// they cover, so that a concurrent insert into a scanned range is detected as a conflict. Once the tracked keys
+/// exceed `limit` bytes, the sets fall back to key hashes and scanned ranges conflict with any write.
+#[derive(Debug, Clone)]
+pub(crate) struct ConflictSet {
+    pub(crate) write_set: KeySet,
+    read_set: KeySet,
+    /// `None` once the ranges have been dropped to bound memory use, which is the same as scanning all keys.
+    read_ranges: Option<Vec<(Bound<Bytes>, Bound<Bytes>)>>,
+    size: usize,
+    limit: usize,
+}
+
+impl ConflictSet {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            write_set: KeySet::default(),
+            read_set: KeySet::default(),
+            read_ranges: Some(Vec::new()),
+            size: 0,
+            limit,
+        }
+    }
+
+    pub(crate) fn add_read(&mut self, key: &[u8]) {
+        self.size += self.read_set.insert(key);
+        self.check_limit();
+    }
+
+    pub(crate) fn add_write(&mut self, key: &[u8]) {
+        self.size += self.write_set.insert(key);
+        self.check_limit();
+    }
+
+    pub(crate) fn add_read_range(&mut self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) {
+        if let Some(read_ranges) = &mut self.read_ranges {
+            let bound_len = |bound: Bound<&[u8]>| match bound {
+                Bound::Included(key) | Bound::Excluded(key) => key.len(),
+                Bound::Unbounded => 0,
+            };
+            self.size += bound_len(lower) + bound_len(upper);
+            read_ranges.push((
+                lower.map(Bytes::copy_from_slice),
+                upper.map(Bytes::copy_from_slice),
+            ));
+            self.check_limit();
+        }
+    }
+
+    fn check_limit(&mut self) {
+        if self.size > self.limit {
+            self.write_set = self.write_set.to_hashed();
+            self.read_set = self.read_set.to_hashed();
+            if self
+                .read_ranges
+                .as_ref()
+                .is_some_and(|ranges| !ranges.is_empty())
+            {
+                self.read_ranges = None;
+            }
+            self.size = 0;
+        }
+    }
+
+    /// Whether any key written by a transaction committed after this one started was read by this transaction.
+    pub(crate) fn conflicts_with(&self, write_set: &KeySet) -> bool {
+        if write_set.is_empty() {
+            return false;
+        }
+        let ranges = match &self.read_ranges {
+            None => return true,
+            Some(ranges) => ranges,
+        };
+        match write_set {
+            KeySet::Exact(keys) => keys.iter().any(|key| {
+                self.read_set.contains(key)
+                    || ranges
+                        .iter()
+                        .any(|(lower, upper)| range_contains(lower, upper, key))
+            }),
+            KeySet::Hashed(hashes) => {
+                // the written keys cannot be checked against the ranges
+                if !ranges.is_empty() {
+                    return true;
+                }
+                match &self.read_set {
+                    KeySet::Exact(keys) => keys
+                        .iter()
+                        .any(|key| hashes.contains(&farmhash::hash32(key))),
+                    KeySet::Hashed(read_hashes) => {
+                        read_hashes.iter().any(|hash| hashes.contains(hash))
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn take_write_set(&mut self) -> KeySet {
+        mem::take(&mut self.write_set)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::new(self.limit);
+    }
+}
+
+fn range_contains(lower: &Bound<Bytes>, upper: &Bound<Bytes>, key: &[u8]) -> bool {
+    let range: (Bound<&[u8]>, Bound<&[u8]>) = (
+        lower.as_ref().map(|x| x.as_ref()),
+        upper.as_ref().map(|x| x.as_ref()),
+    );
+    RangeBounds::<[u8]>::contains(&range, key)
+}
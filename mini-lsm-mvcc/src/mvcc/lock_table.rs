@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+
+#[derive(Default)]
+struct LockTableState {
+    /// The transaction holding the lock of each key.
+    owners: HashMap<Bytes, u64>,
+    /// The transaction each waiting transaction is waiting for.
+    waits_for: HashMap<u64, u64>,
+}
+
+/// Exclusive per-key locks for pessimistic transactions.
+#[derive(Default)]
+pub struct LockTable {
+    state: Mutex<LockTableState>,
+    released: Condvar,
+}
+
+impl LockTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Acquire the lock of `key` for transaction `txn_id`, waiting at most `timeout`. Returns whether the lock was
+    /// newly acquired, i.e., false if the transaction already holds it. Fails immediately if waiting would
+    /// deadlock.
+    pub fn lock(&self, txn_id: u64, key: &[u8], timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock();
+        loop {
+            let owner = match state.owners.get(key) {
+                None => {
+                    state.waits_for.remove(&txn_id);
+                    state.owners.insert(Bytes::copy_from_slice(key), txn_id);
+                    return Ok(true);
+                }
+                Some(owner) if *owner == txn_id => return Ok(false),
+                Some(owner) => *owner,
+            };
+            // follow the chain of waiting transactions, a cycle back to us is a deadlock
+            let mut current = owner;
+            while let Some(next) = state.waits_for.get(&current) {
+                if *next == txn_id {
+                    state.waits_for.remove(&txn_id);
+                    bail!("deadlock detected while waiting for a lock");
+                }
+                current = *next;
+            }
+            state.waits_for.insert(txn_id, owner);
+            if self.released.wait_until(&mut state, deadline).timed_out()
+                && state.owners.contains_key(key)
+            {
+                state.waits_for.remove(&txn_id);
+                bail!("timed out waiting for a lock after {:?}", timeout);
+            }
+        }
+    }
+
+    /// Release the locks of `keys` held by transaction `txn_id`.
+    pub fn unlock<'a>(&self, txn_id: u64, keys: impl IntoIterator<Item = &'a Bytes>) {
+        let mut state = self.state.lock();
+        for key in keys {
+            if state.owners.get(key) == Some(&txn_id) {
+                state.owners.remove(key);
+            }
+        }
+        self.released.notify_all();
+    }
+
+    pub fn num_locked_keys(&self) -> usize {
+        self.state.lock().owners.len()
+    }
+}
@@ -0,0 +1,99 @@
+use std::{ops::Bound, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_iterator::{FusedIterator, LsmIterator},
+    lsm_storage::LsmStorageInner,
+};
+
+struct SnapshotInner {
+    read_ts: u64,
+    inner: Arc<LsmStorageInner>,
+}
+
+impl Drop for SnapshotInner {
+    fn drop(&mut self) {
+        self.inner.mvcc().ts.lock().1.remove_reader(self.read_ts)
+    }
+}
+
+/// A read-only view of the storage at a fixed timestamp. Unlike `Transaction`, a snapshot does not buffer writes or
+/// track read sets. Clones share the same `read_ts`, which is pinned in the watermark until the last clone is dropped.
+#[derive(Clone)]
+pub struct Snapshot {
+    inner: Arc<SnapshotInner>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(inner: Arc<LsmStorageInner>, read_ts: u64) -> Self {
+        Self {
+            inner: Arc::new(SnapshotInner { read_ts, inner }),
+        }
+    }
+
+    pub fn read_ts(&self) -> u64 {
+        self.inner.read_ts
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.inner.get_with_ts(key, self.inner.read_ts)
+    }
+
+    /// Get multiple keys at once. Results are returned in input order.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.inner.inner.multi_get_with_ts(keys, self.inner.read_ts)
+    }
+
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<SnapshotIterator> {
+        Ok(SnapshotIterator {
+            iter: self
+                .inner
+                .inner
+                .scan_with_ts(lower, upper, self.inner.read_ts)?,
+            snapshot: self.clone(),
+        })
+    }
+}
+
+/// An iterator over a snapshot. It keeps the snapshot alive until the iterator is dropped.
+pub struct SnapshotIterator {
+    iter: FusedIterator<LsmIterator>,
+    snapshot: Snapshot,
+}
+
+impl SnapshotIterator {
+    pub fn read_ts(&self) -> u64 {
+        self.snapshot.read_ts()
+    }
+}
+
+impl StorageIterator for SnapshotIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
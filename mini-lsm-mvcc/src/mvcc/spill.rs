@@ -0,0 +1,176 @@
+use std::{ops::Bound, path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+
+use crate::{
+    iterators::{
+        merge_iterator::MergeIterator, two_merge_iterator::TwoMergeIterator, StorageIterator,
+    },
+    key::{KeySlice, TS_RANGE_BEGIN},
+    lsm_storage::LsmStorageInner,
+    table::{SsTable, SsTableBuilder, SsTableIterator},
+};
+
+use super::txn::TxnLocalIterator;
+
+/// A sorted run of transaction-local writes spilled to a temporary SST. Keys are stored at ts 0, and deletes as
+/// empty values. The file is removed once no transaction or iterator uses the run.
+pub(crate) struct SpillRun {
+    table: Arc<SsTable>,
+    path: PathBuf,
+}
+
+impl SpillRun {
+    fn build(inner: &LsmStorageInner, memory: &SkipMap<Bytes, Bytes>) -> Result<Self> {
+        let mut builder = SsTableBuilder::new(inner.options.block_size);
+        for entry in memory.iter() {
+            builder.add(KeySlice::from_slice(entry.key(), 0), entry.value());
+        }
+        let id = inner.next_sst_id();
+        let path = inner.path_of_spill(id);
+        let table = builder.build(id, None, &path)?;
+        Ok(Self {
+            table: Arc::new(table),
+            path,
+        })
+    }
+}
+
+impl Drop for SpillRun {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The writes of a transaction: the most recent ones in memory, and older ones in sorted runs on disk.
+#[derive(Clone, Default)]
+pub(crate) struct LocalStorage {
+    pub(crate) memory: Arc<SkipMap<Bytes, Bytes>>,
+    /// Newest first, a key in an earlier run shadows the same key in later runs.
+    pub(crate) spilled: Vec<Arc<SpillRun>>,
+    /// The approximate size of the in-memory part.
+    pub(crate) size: usize,
+}
+
+impl LocalStorage {
+    /// Get the local write of a key, which is an empty value if the key is deleted.
+    pub(crate) fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        if let Some(entry) = self.memory.get(key) {
+            return Ok(Some(entry.value().clone()));
+        }
+        if self.spilled.is_empty() {
+            return Ok(None);
+        }
+        let keys = [(key, farmhash::fingerprint32(key))];
+        for run in &self.spilled {
+            if let Some(value) = run.table.multi_get_with_ts(&keys, TS_RANGE_BEGIN)?.pop() {
+                if value.is_some() {
+                    return Ok(value);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Write the in-memory part to a new sorted run, and continue with an empty memory. Iterators created before
+    /// keep reading the old in-memory part.
+    pub(crate) fn spill(&mut self, inner: &LsmStorageInner) -> Result<()> {
+        if self.memory.is_empty() {
+            return Ok(());
+        }
+        let run = SpillRun::build(inner, &self.memory)?;
+        self.spilled.insert(0, Arc::new(run));
+        self.memory = Arc::new(SkipMap::new());
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Copy the in-memory part, sharing the spilled runs which are never modified.
+    pub(crate) fn copy(&self) -> Self {
+        let memory = SkipMap::new();
+        for entry in self.memory.iter() {
+            memory.insert(entry.key().clone(), entry.value().clone());
+        }
+        Self {
+            memory: Arc::new(memory),
+            spilled: self.spilled.clone(),
+            size: self.size,
+        }
+    }
+
+    pub(crate) fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<LocalStorageIterator> {
+        TwoMergeIterator::create(
+            TxnLocalIterator::create(self.memory.clone(), lower, upper),
+            SpillIterator::create(&self.spilled, lower, upper)?,
+        )
+    }
+}
+
+/// The merged local writes of a transaction, with deletes as empty values.
+pub type LocalStorageIterator = TwoMergeIterator<TxnLocalIterator, SpillIterator>;
+
+/// Iterates over the spilled runs of a transaction by user key.
+pub struct SpillIterator {
+    iter: MergeIterator<SsTableIterator>,
+}
+
+impl SpillIterator {
+    fn create(runs: &[Arc<SpillRun>], lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<Self> {
+        let mut iters = Vec::with_capacity(runs.len());
+        for run in runs {
+            let iter = match lower {
+                Bound::Included(key) | Bound::Excluded(key) => {
+                    SsTableIterator::create_and_seek_to_key(
+                        run.table.clone(),
+                        KeySlice::from_slice(key, TS_RANGE_BEGIN),
+                    )?
+                }
+                Bound::Unbounded => SsTableIterator::create_and_seek_to_first(run.table.clone())?,
+            };
+            iters.push(Box::new(iter.with_upper_bound(upper)));
+        }
+        let mut iter = Self {
+            iter: MergeIterator::create(iters),
+        };
+        if let Bound::Excluded(key) = lower {
+            if iter.is_valid() && iter.key() == key {
+                iter.next()?;
+            }
+        }
+        Ok(iter)
+    }
+}
+
+impl StorageIterator for SpillIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key().key_ref()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(KeySlice::from_slice(key, TS_RANGE_BEGIN))
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
@@ -0,0 +1,132 @@
+use std::{ops::Bound, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_iterator::{FusedIterator, LsmIterator},
+    lsm_storage::LsmStorageInner,
+    mem_table::map_bound,
+};
+
+use super::snapshot::Snapshot;
+
+/// An iterator that follows the writes committed after it was created. It reads a snapshot like a normal scan,
+/// and once it is exhausted, `poll` picks up the keys committed since then that sort after the last position of
+/// the iterator, including keys in memtables created after a freeze. Keys before the last position, including
+/// new versions of keys already returned, are not revisited.
+pub struct TailingIterator {
+    inner: Arc<LsmStorageInner>,
+    iter: FusedIterator<LsmIterator>,
+    /// The snapshot the iterator is currently reading, all versions at or below its read ts in the range have been
+    /// read once the iterator is exhausted.
+    snapshot: Snapshot,
+    /// Where to resume the scan when the iterator is refreshed.
+    resume_from: Bound<Bytes>,
+    upper: Bound<Bytes>,
+}
+
+impl TailingIterator {
+    pub(crate) fn create(
+        inner: Arc<LsmStorageInner>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Self> {
+        let snapshot = inner.snapshot();
+        let upper = map_bound(upper);
+        let iter = Self::scan(&inner, &snapshot, lower, &upper, None)?;
+        Ok(Self {
+            inner,
+            iter,
+            snapshot,
+            resume_from: map_bound(lower),
+            upper,
+        })
+    }
+
+    fn scan(
+        inner: &LsmStorageInner,
+        snapshot: &Snapshot,
+        lower: Bound<&[u8]>,
+        upper: &Bound<Bytes>,
+        min_ts: Option<u64>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let iter =
+            inner.scan_versions_newer_than(lower, upper.as_ref().map(|x| x.as_ref()), min_ts)?;
+        Ok(FusedIterator::new(LsmIterator::new(
+            iter,
+            upper.clone(),
+            snapshot.read_ts(),
+        )?))
+    }
+
+    /// The read ts of the snapshot the iterator is currently reading.
+    pub fn read_ts(&self) -> u64 {
+        self.snapshot.read_ts()
+    }
+
+    /// Check for writes committed after the current snapshot and return whether the iterator is valid. This is a
+    /// no-op if the iterator is still valid or nothing has been committed since. Otherwise, the scan is resumed
+    /// past the last position on a new snapshot. The SSTs read by the exhausted scan cannot hold anything newer,
+    /// so only the memtables, including the ones created since, and the SSTs with versions above the previous read
+    /// ts, such as flushed memtables and ingested files, are read again.
+    pub fn poll(&mut self) -> Result<bool> {
+        if self.iter.is_valid() {
+            return Ok(true);
+        }
+        if self.inner.mvcc().latest_commit_ts() == self.read_ts() {
+            return Ok(false);
+        }
+        let snapshot = self.inner.snapshot();
+        self.iter = Self::scan(
+            &self.inner,
+            &snapshot,
+            self.resume_from.as_ref().map(|x| x.as_ref()),
+            &self.upper,
+            Some(self.read_ts()),
+        )?;
+        self.snapshot = snapshot;
+        Ok(self.iter.is_valid())
+    }
+}
+
+impl StorageIterator for TailingIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.iter.is_valid() {
+            self.resume_from = Bound::Excluded(Bytes::copy_from_slice(self.iter.key()));
+        }
+        self.iter.next()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        // the scan after a poll skips the SSTs that were read already, which may hold keys before the last position
+        self.resume_from = Bound::Included(Bytes::copy_from_slice(key));
+        self.iter = Self::scan(
+            &self.inner,
+            &self.snapshot,
+            Bound::Included(key),
+            &self.upper,
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
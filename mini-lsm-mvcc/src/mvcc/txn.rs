@@ -1,132 +1,406 @@
 use std::{
-    collections::HashSet,
     ops::Bound,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use anyhow::{bail, Result};
 use bytes::Bytes;
 use crossbeam_skiplist::{map::Entry, SkipMap};
 use ouroboros::self_referencing;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 
 use crate::{
     iterators::{two_merge_iterator::TwoMergeIterator, StorageIterator},
     lsm_iterator::{FusedIterator, LsmIterator},
-    lsm_storage::{LsmStorageInner, WriteBatchRecord},
+    lsm_storage::{prefix_upper_bound, LsmStorageInner, WriteBatchRecord, WriteOptions},
     mem_table::map_bound,
-    mvcc::CommittedTxnData,
+    mvcc::{
+        commit_queue::CommitRequest,
+        conflict::ConflictSet,
+        spill::{LocalStorage, LocalStorageIterator},
+    },
 };
 
+/// The isolation level of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IsolationLevel {
+    /// Each read sees the latest commit. Writes are not checked for conflicts.
+    ReadCommitted,
+    /// All reads see the snapshot at the start of the transaction. Writes are not checked for conflicts.
+    #[default]
+    Snapshot,
+    /// Snapshot reads, and the commit fails if anything the transaction read was written by a concurrent commit.
+    Serializable,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TxnOptions {
+    pub isolation: IsolationLevel,
+    /// Read-only transactions skip the conflict bookkeeping of serializable isolation, and cannot write. This is
+    /// optional: a serializable transaction that is not declared read-only tracks its reads, as a later write would
+    /// need them, but if it wrote nothing, it is detected as read-only on commit, which then neither checks nor
+    /// records conflicts.
+    pub read_only: bool,
+}
+
 pub struct Transaction {
     pub(crate) read_ts: u64,
+    pub(crate) isolation: IsolationLevel,
+    pub(crate) read_only: bool,
     pub(crate) inner: Arc<LsmStorageInner>,
-    pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
+    /// Local writes beyond `txn_spill_threshold` bytes are spilled to sorted runs on disk.
+    pub(crate) local_storage: RwLock<LocalStorage>,
     pub(crate) committed: Arc<AtomicBool>,
-    /// Write set and read set
-    pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
+    /// Write set and read set, only tracked for serializable transactions
+    pub(crate) conflict_set: Option<Mutex<ConflictSet>>,
+    /// Whether the transaction is counted in `active_serializable_txns`, until it commits or is dropped.
+    pub(crate) counted_serializable: AtomicBool,
+    /// The stack of savepoints, the last one is the most recent.
+    pub(crate) savepoints: Mutex<Vec<Savepoint>>,
+    pub(crate) txn_id: u64,
+    /// Pessimistic transactions lock the keys they write or read with `get_for_update`.
+    pub(crate) lock_timeout: Option<Duration>,
+    pub(crate) locked_keys: Mutex<Vec<Bytes>>,
+    /// The first failure of an infallible `put` or `delete`, reported on commit.
+    pub(crate) write_error: Mutex<Option<anyhow::Error>>,
+}
+
+/// A copy of the local state of a transaction, to be restored by `Transaction::rollback_to_savepoint`.
+pub(crate) struct Savepoint {
+    local_storage: LocalStorage,
+    conflict_set: Option<ConflictSet>,
+    /// Whether a deferred write failure was recorded before the savepoint.
+    write_error: bool,
 }
 
 impl Transaction {
+    /// The ts reads are served at, which follows the latest commit under read-committed isolation.
+    fn current_read_ts(&self) -> u64 {
+        match self.isolation {
+            IsolationLevel::ReadCommitted => self.inner.mvcc().latest_commit_ts(),
+            IsolationLevel::Snapshot | IsolationLevel::Serializable => self.read_ts,
+        }
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        if let Some(guard) = &self.key_hashes {
+        if let Some(guard) = &self.conflict_set {
+            guard.lock().add_read(key);
+        }
+        if let Some(value) = self.local_storage.read().get(key)? {
+            return Ok(Some(value).filter(|value| !value.is_empty()));
+        }
+        self.inner.get_with_ts(key, self.current_read_ts())
+    }
+
+    /// Get multiple keys at once. Results are returned in input order.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        if let Some(guard) = &self.conflict_set {
             let mut guard = guard.lock();
-            let (_, read_set) = &mut *guard;
-            read_set.insert(farmhash::hash32(key));
+            for key in keys {
+                guard.add_read(key);
+            }
         }
-        if let Some(entry) = self.local_storage.get(key) {
-            if entry.value().is_empty() {
-                return Ok(None);
-            } else {
-                return Ok(Some(entry.value().clone()));
+        let mut result = vec![None; keys.len()];
+        let mut storage_keys = Vec::with_capacity(keys.len());
+        {
+            let local_storage = self.local_storage.read();
+            for (idx, key) in keys.iter().enumerate() {
+                if let Some(value) = local_storage.get(key)? {
+                    result[idx] = Some(value).filter(|value| !value.is_empty());
+                } else {
+                    storage_keys.push(idx);
+                }
             }
         }
-        self.inner.get_with_ts(key, self.read_ts)
+        let values = self.inner.multi_get_with_ts(
+            &storage_keys
+                .iter()
+                .map(|idx| keys[*idx])
+                .collect::<Vec<_>>(),
+            self.current_read_ts(),
+        )?;
+        for (idx, value) in storage_keys.into_iter().zip(values) {
+            result[idx] = value;
+        }
+        Ok(result)
     }
 
     pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        let mut local_iter = TxnLocalIteratorBuilder {
-            map: self.local_storage.clone(),
-            iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
-            item: (Bytes::new(), Bytes::new()),
+        // record the whole range, so that keys inserted into it by other transactions are conflicts
+        if let Some(guard) = &self.conflict_set {
+            guard.lock().add_read_range(lower, upper);
         }
-        .build();
-        let entry = local_iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
-        local_iter.with_mut(|x| *x.item = entry);
-
+        let local_iter = self.local_storage.read().scan(lower, upper)?;
         TxnIterator::create(
             self.clone(),
             TwoMergeIterator::create(
                 local_iter,
-                self.inner.scan_with_ts(lower, upper, self.read_ts)?,
+                self.inner
+                    .scan_with_ts(lower, upper, self.current_read_ts())?,
             )?,
         )
     }
 
+    /// Create an iterator over all keys starting with `prefix`.
+    pub fn prefix_scan(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        let upper = prefix_upper_bound(prefix);
+        self.scan(
+            Bound::Included(prefix),
+            upper.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+        )
+    }
+
+    /// Read a key and lock it until the transaction ends. Under pessimistic locking, the latest committed value is
+    /// returned, as no other transaction can modify the key while the lock is held. Otherwise, this is the same as
+    /// `get`, and conflicts are detected on commit.
+    pub fn get_for_update(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        if self.lock_timeout.is_none() {
+            return self.get(key);
+        }
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.lock_key(key)?;
+        if let Some(value) = self.local_storage.read().get(key)? {
+            return Ok(Some(value).filter(|value| !value.is_empty()));
+        }
+        self.inner
+            .get_with_ts(key, self.inner.mvcc().latest_commit_ts())
+    }
+
+    /// A failed write, e.g., a lock failure under pessimistic locking, is reported when the transaction commits. Use
+    /// `try_put` to handle it immediately.
     pub fn put(&self, key: &[u8], value: &[u8]) {
+        if let Err(e) = self.try_put(key, value) {
+            self.write_error.lock().get_or_insert(e);
+        }
+    }
+
+    /// A failed write, e.g., a lock failure under pessimistic locking, is reported when the transaction commits. Use
+    /// `try_delete` to handle it immediately.
+    pub fn delete(&self, key: &[u8]) {
+        if let Err(e) = self.try_delete(key) {
+            self.write_error.lock().get_or_insert(e);
+        }
+    }
+
+    /// Put a key-value pair, returning an error if the key cannot be locked or the transaction is read-only. The
+    /// write is not applied on error.
+    pub fn try_put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        self.local_storage
+        if self.read_only {
+            bail!("cannot write in a read-only txn");
+        }
+        self.lock_key(key)?;
+        self.write_local(key, value)?;
+        if let Some(guard) = &self.conflict_set {
+            guard.lock().add_write(key);
+        }
+        Ok(())
+    }
+
+    /// Delete a key, returning an error if the key cannot be locked or the transaction is read-only. The write is
+    /// not applied on error.
+    pub fn try_delete(&self, key: &[u8]) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        if self.read_only {
+            bail!("cannot write in a read-only txn");
+        }
+        self.lock_key(key)?;
+        self.write_local(key, b"")?;
+        if let Some(guard) = &self.conflict_set {
+            guard.lock().add_write(key);
+        }
+        Ok(())
+    }
+
+    /// Write to the local storage, spilling the in-memory writes to disk first if they grow beyond the threshold.
+    fn write_local(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut local_storage = self.local_storage.write();
+        let size = key.len() + value.len();
+        if local_storage.size + size > self.inner.options.txn_spill_threshold {
+            local_storage.spill(&self.inner)?;
+        }
+        local_storage
+            .memory
             .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
-        if let Some(key_hashes) = &self.key_hashes {
-            let mut key_hashes = key_hashes.lock();
-            let (write_hashes, _) = &mut *key_hashes;
-            write_hashes.insert(farmhash::hash32(key));
+        local_storage.size += size;
+        Ok(())
+    }
+
+    /// Lock a key until the transaction ends, only for pessimistic transactions.
+    fn lock_key(&self, key: &[u8]) -> Result<()> {
+        let Some(timeout) = self.lock_timeout else {
+            return Ok(());
+        };
+        if self
+            .inner
+            .mvcc()
+            .lock_table
+            .lock(self.txn_id, key, timeout)?
+        {
+            self.locked_keys.lock().push(Bytes::copy_from_slice(key));
         }
+        Ok(())
     }
 
-    pub fn delete(&self, key: &[u8]) {
+    fn release_locks(&self) {
+        let locked_keys = std::mem::take(&mut *self.locked_keys.lock());
+        if !locked_keys.is_empty() {
+            self.inner
+                .mvcc()
+                .lock_table
+                .unlock(self.txn_id, &locked_keys);
+        }
+    }
+
+    /// Record the current local writes and read/write sets. Savepoints form a stack, and setting a savepoint copies
+    /// the local state of the transaction.
+    pub fn set_savepoint(&self) {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        self.local_storage
-            .insert(Bytes::copy_from_slice(key), Bytes::new());
-        if let Some(key_hashes) = &self.key_hashes {
-            let mut key_hashes = key_hashes.lock();
-            let (write_hashes, _) = &mut *key_hashes;
-            write_hashes.insert(farmhash::hash32(key));
+        let savepoint = Savepoint {
+            local_storage: self.local_storage.read().copy(),
+            conflict_set: self.conflict_set.as_ref().map(|guard| guard.lock().clone()),
+            write_error: self.write_error.lock().is_some(),
+        };
+        self.savepoints.lock().push(savepoint);
+    }
+
+    /// Undo everything done since the most recent savepoint, and remove that savepoint.
+    pub fn rollback_to_savepoint(&self) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        let Some(savepoint) = self.savepoints.lock().pop() else {
+            bail!("no savepoint to roll back to");
+        };
+        *self.local_storage.write() = savepoint.local_storage;
+        if let Some(guard) = &self.conflict_set {
+            *guard.lock() = savepoint.conflict_set.unwrap();
+        }
+        if !savepoint.write_error {
+            // a failed write after the savepoint is undone along with the other writes
+            self.write_error.lock().take();
+        }
+        Ok(())
+    }
+
+    /// Discard all local writes, read/write sets, savepoints and locks. The transaction can still be used, and
+    /// keeps reading at the same `read_ts` until it is dropped.
+    pub fn rollback(&self) {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.savepoints.lock().clear();
+        *self.local_storage.write() = LocalStorage::default();
+        self.write_error.lock().take();
+        self.release_locks();
+        if let Some(guard) = &self.conflict_set {
+            guard.lock().clear();
         }
     }
 
     pub fn commit(&self) -> Result<()> {
+        self.commit_with_options(WriteOptions::default())
+    }
+
+    /// Commit the transaction, e.g., returning only after the commit is on stable storage with `WriteOptions::sync`.
+    pub fn commit_with_options(&self, options: WriteOptions) -> Result<()> {
+        let result = self.commit_inner(options);
+        self.release_locks();
+        self.uncount_serializable();
+        result
+    }
+
+    fn commit_inner(&self, options: WriteOptions) -> Result<()> {
         self.committed
             .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
             .expect("cannot operate on committed txn!");
-        let _commit_lock = self.inner.mvcc().commit_lock.lock();
-        let serializability_check;
-        if let Some(guard) = &self.key_hashes {
-            let guard = guard.lock();
-            let (write_set, read_set) = &*guard;
-            println!(
-                "commit txn: write_set: {:?}, read_set: {:?}",
-                write_set, read_set
-            );
+        if let Some(e) = self.write_error.lock().take() {
+            return Err(e);
+        }
+        let local_storage = self.local_storage.read().clone();
+        if local_storage.spilled.is_empty() {
+            return self.commit_through_queue(&local_storage, options);
+        }
+        let mvcc = self.inner.mvcc();
+        let _commit_lock = mvcc.commit_lock.lock();
+        if let Some(guard) = &self.conflict_set {
+            mvcc.check_conflicts(self.read_ts, &guard.lock())?;
+        }
+        // stream the merged local writes, so that the spilled ones are not buffered again before the memtable
+        let mut iter = local_storage.scan(Bound::Unbounded, Bound::Unbounded)?;
+        let records = std::iter::from_fn(|| {
+            if !iter.is_valid() {
+                return None;
+            }
+            let record = if iter.value().is_empty() {
+                WriteBatchRecord::Del(Bytes::copy_from_slice(iter.key()))
+            } else {
+                WriteBatchRecord::Put(
+                    Bytes::copy_from_slice(iter.key()),
+                    Bytes::copy_from_slice(iter.value()),
+                )
+            };
+            Some(iter.next().map(|_| record))
+        });
+        let ts = self.inner.write_records_inner(records, options)?;
+        if let Some(guard) = &self.conflict_set {
+            let write_set = guard.lock().take_write_set();
+            mvcc.record_commit(ts, self.read_ts, write_set);
+        } else if mvcc.has_serializable_txns() {
+            // concurrent serializable transactions need to know what non-serializable commits wrote
+            let mut conflict_set = ConflictSet::new(self.inner.options.conflict_tracking_limit);
+            let mut iter = local_storage.scan(Bound::Unbounded, Bound::Unbounded)?;
+            while iter.is_valid() {
+                conflict_set.add_write(iter.key());
+                iter.next()?;
+            }
+            let write_set = conflict_set.take_write_set();
             if !write_set.is_empty() {
-                let committed_txns = self.inner.mvcc().committed_txns.lock();
-                for (_, txn_data) in committed_txns.range((self.read_ts + 1)..) {
-                    for key_hash in read_set {
-                        if txn_data.key_hashes.contains(key_hash) {
-                            bail!("serializable check failed");
-                        }
-                    }
-                }
+                mvcc.record_commit(ts, self.read_ts, write_set);
             }
-            serializability_check = true;
-        } else {
-            serializability_check = false;
         }
-        let batch = self
-            .local_storage
+        Ok(())
+    }
+
+    /// Once the transaction has committed, other commits need not be recorded for its conflict check.
+    fn uncount_serializable(&self) {
+        if self.counted_serializable.swap(false, Ordering::SeqCst) {
+            self.inner
+                .mvcc()
+                .active_serializable_txns
+                .fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Commit the in-memory local writes as part of a group commit.
+    fn commit_through_queue(
+        &self,
+        local_storage: &LocalStorage,
+        options: WriteOptions,
+    ) -> Result<()> {
+        let batch = local_storage
+            .memory
             .iter()
             .map(|entry| {
                 if entry.value().is_empty() {
@@ -135,39 +409,30 @@ impl Transaction {
                     WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
                 }
             })
-            .collect::<Vec<_>>();
-        let ts = self.inner.write_batch_inner(&batch)?;
-        if serializability_check {
-            let mut committed_txns = self.inner.mvcc().committed_txns.lock();
-            let mut key_hashes = self.key_hashes.as_ref().unwrap().lock();
-            let (write_set, _) = &mut *key_hashes;
-
-            let old_data = committed_txns.insert(
-                ts,
-                CommittedTxnData {
-                    key_hashes: std::mem::take(write_set),
-                    read_ts: self.read_ts,
-                    commit_ts: ts,
-                },
-            );
-            assert!(old_data.is_none());
-
-            // remove unneeded txn data
-            let watermark = self.inner.mvcc().watermark();
-            while let Some(entry) = committed_txns.first_entry() {
-                if *entry.key() < watermark {
-                    entry.remove();
-                } else {
-                    break;
-                }
-            }
-        }
+            .collect();
+        // a transaction that wrote nothing is read-only, and its reads cannot conflict
+        let conflict_set = self
+            .conflict_set
+            .as_ref()
+            .map(|guard| {
+                let limit = self.inner.options.conflict_tracking_limit;
+                std::mem::replace(&mut *guard.lock(), ConflictSet::new(limit))
+            })
+            .filter(|_| !local_storage.memory.is_empty());
+        self.inner.commit(CommitRequest::new(
+            batch,
+            options,
+            Some(self.read_ts),
+            conflict_set,
+        ))?;
         Ok(())
     }
 }
 
 impl Drop for Transaction {
     fn drop(&mut self) {
+        self.release_locks();
+        self.uncount_serializable();
         self.inner.mvcc().ts.lock().1.remove_reader(self.read_ts)
     }
 }
@@ -185,9 +450,28 @@ pub struct TxnLocalIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (Bytes, Bytes),
+    /// Stores the upper bound of the range, so that the iterator can be re-seeked.
+    upper: Bound<Bytes>,
 }
 
 impl TxnLocalIterator {
+    pub(crate) fn create(
+        map: Arc<SkipMap<Bytes, Bytes>>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Self {
+        let mut iter = TxnLocalIteratorBuilder {
+            map,
+            iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
+            item: (Bytes::new(), Bytes::new()),
+            upper: map_bound(upper),
+        }
+        .build();
+        let entry = iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
+        iter.with_mut(|x| *x.item = entry);
+        iter
+    }
+
     fn entry_to_item(entry: Option<Entry<'_, Bytes, Bytes>>) -> (Bytes, Bytes) {
         entry
             .map(|x| (x.key().clone(), x.value().clone()))
@@ -215,23 +499,26 @@ impl StorageIterator for TxnLocalIterator {
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let lower = Bound::Included(Bytes::copy_from_slice(key));
+        self.with_mut(|x| *x.iter = x.map.range((lower, x.upper.clone())));
+        self.next()
+    }
 }
 
 pub struct TxnIterator {
     txn: Arc<Transaction>,
-    iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    iter: TwoMergeIterator<LocalStorageIterator, FusedIterator<LsmIterator>>,
 }
 
 impl TxnIterator {
     pub fn create(
         txn: Arc<Transaction>,
-        iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+        iter: TwoMergeIterator<LocalStorageIterator, FusedIterator<LsmIterator>>,
     ) -> Result<Self> {
         let mut iter = Self { txn, iter };
         iter.skip_deletes()?;
-        if iter.is_valid() {
-            iter.add_to_read_set(iter.key());
-        }
         Ok(iter)
     }
 
@@ -241,14 +528,6 @@ impl TxnIterator {
         }
         Ok(())
     }
-
-    fn add_to_read_set(&self, key: &[u8]) {
-        if let Some(guard) = &self.txn.key_hashes {
-            let mut guard = guard.lock();
-            let (_, read_set) = &mut *guard;
-            read_set.insert(farmhash::hash32(key));
-        }
-    }
 }
 
 impl StorageIterator for TxnIterator {
@@ -268,11 +547,12 @@ impl StorageIterator for TxnIterator {
 
     fn next(&mut self) -> Result<()> {
         self.iter.next()?;
-        self.skip_deletes()?;
-        if self.is_valid() {
-            self.add_to_read_set(self.key());
-        }
-        Ok(())
+        self.skip_deletes()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)?;
+        self.skip_deletes()
     }
 
     fn num_active_iterators(&self) -> usize {
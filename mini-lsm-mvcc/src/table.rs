@@ -8,10 +8,11 @@ use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Result};
 pub use builder::SsTableBuilder;
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
+pub(crate) use iterator::key_within_upper_bound;
 pub use iterator::SsTableIterator;
 
-use crate::block::Block;
+use crate::block::{Block, BlockIterator};
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
 
@@ -29,7 +30,12 @@ pub struct BlockMeta {
 
 impl BlockMeta {
     /// Encode block meta to a buffer.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], max_ts: u64, buf: &mut Vec<u8>) {
+    pub fn encode_block_meta(
+        block_meta: &[BlockMeta],
+        min_ts: u64,
+        max_ts: u64,
+        buf: &mut Vec<u8>,
+    ) {
         let mut estimated_size = std::mem::size_of::<u32>(); // number of blocks
         for meta in block_meta {
             // The size of offset
@@ -43,6 +49,7 @@ impl BlockMeta {
             // The size of actual key
             estimated_size += meta.last_key.raw_len();
         }
+        estimated_size += std::mem::size_of::<u64>(); // min timestamp
         estimated_size += std::mem::size_of::<u64>(); // max timestamp
         estimated_size += std::mem::size_of::<u32>(); // checksum
 
@@ -60,13 +67,14 @@ impl BlockMeta {
             buf.put_slice(meta.last_key.key_ref());
             buf.put_u64(meta.last_key.ts());
         }
+        buf.put_u64(min_ts);
         buf.put_u64(max_ts);
         buf.put_u32(crc32fast::hash(&buf[original_len + 4..]));
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    /// Decode block meta from a buffer, together with the min and max timestamps.
+    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64, u64)> {
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
         let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
@@ -84,12 +92,13 @@ impl BlockMeta {
                 last_key,
             });
         }
+        let min_ts = buf.get_u64();
         let max_ts = buf.get_u64();
         if buf.get_u32() != checksum {
             bail!("meta checksum mismatched");
         }
 
-        Ok((block_meta, max_ts))
+        Ok((block_meta, min_ts, max_ts))
     }
 }
 
@@ -141,6 +150,7 @@ pub struct SsTable {
     first_key: KeyBytes,
     last_key: KeyBytes,
     pub(crate) bloom: Option<Bloom>,
+    min_ts: u64,
     max_ts: u64,
 }
 impl SsTable {
@@ -159,7 +169,7 @@ impl SsTable {
         let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
         let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
         let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let (block_meta, min_ts, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
         Ok(Self {
             file,
             first_key: block_meta.first().unwrap().first_key.clone(),
@@ -169,6 +179,7 @@ impl SsTable {
             id,
             block_cache,
             bloom: Some(bloom_filter),
+            min_ts,
             max_ts,
         })
     }
@@ -189,6 +200,7 @@ impl SsTable {
             first_key,
             last_key,
             bloom: None,
+            min_ts: 0,
             max_ts: 0,
         }
     }
@@ -231,6 +243,52 @@ impl SsTable {
             .saturating_sub(1)
     }
 
+    /// Batched point lookup. `keys` are sorted user keys together with their bloom filter hashes. For each key, find
+    /// the latest version visible at `read_ts`, which is an empty value if the key is deleted. Returns `None` for keys
+    /// without a visible version in this SST. Consecutive keys in the same block share one decoded block.
+    pub(crate) fn multi_get_with_ts(
+        &self,
+        keys: &[(&[u8], u32)],
+        read_ts: u64,
+    ) -> Result<Vec<Option<Bytes>>> {
+        let mut result = vec![None; keys.len()];
+        let mut last_block: Option<(usize, Arc<Block>)> = None;
+        let mut load_block = |block_idx: usize| -> Result<Arc<Block>> {
+            if let Some((idx, block)) = &last_block {
+                if *idx == block_idx {
+                    return Ok(block.clone());
+                }
+            }
+            let block = self.read_block_cached(block_idx)?;
+            last_block = Some((block_idx, block.clone()));
+            Ok(block)
+        };
+        for (idx, (key, hash)) in keys.iter().enumerate() {
+            if *key < self.first_key.key_ref() || *key > self.last_key.key_ref() {
+                continue;
+            }
+            if let Some(bloom) = &self.bloom {
+                if !bloom.may_contain(*hash) {
+                    continue;
+                }
+            }
+            let seek_key = KeySlice::from_slice(key, read_ts);
+            let mut block_idx = self.find_block_idx(seek_key);
+            let mut iter = BlockIterator::create_and_seek_to_key(load_block(block_idx)?, seek_key);
+            if !iter.is_valid() {
+                block_idx += 1;
+                if block_idx >= self.num_of_blocks() {
+                    continue;
+                }
+                iter = BlockIterator::create_and_seek_to_first(load_block(block_idx)?);
+            }
+            if iter.key().key_ref() == *key {
+                result[idx] = Some(Bytes::copy_from_slice(iter.value()));
+            }
+        }
+        Ok(result)
+    }
+
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
         self.block_meta.len()
@@ -252,6 +310,10 @@ impl SsTable {
         self.id
     }
 
+    pub fn min_ts(&self) -> u64 {
+        self.min_ts
+    }
+
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
@@ -19,6 +19,7 @@ pub struct SsTableBuilder {
     pub(crate) meta: Vec<BlockMeta>,
     block_size: usize,
     key_hashes: Vec<u32>,
+    min_ts: u64,
     max_ts: u64,
 }
 
@@ -33,6 +34,7 @@ impl SsTableBuilder {
             block_size,
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
+            min_ts: u64::MAX,
             max_ts: 0,
         }
     }
@@ -43,6 +45,7 @@ impl SsTableBuilder {
             self.first_key.set_from_slice(key);
         }
 
+        self.min_ts = self.min_ts.min(key.ts());
         if key.ts() > self.max_ts {
             self.max_ts = key.ts();
         }
@@ -90,7 +93,7 @@ impl SsTableBuilder {
         self.finish_block();
         let mut buf = self.data;
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
+        BlockMeta::encode_block_meta(&self.meta, self.min_ts, self.max_ts, &mut buf);
         buf.put_u32(meta_offset as u32);
         let bloom = Bloom::build_from_key_hashes(
             &self.key_hashes,
@@ -109,6 +112,7 @@ impl SsTableBuilder {
             block_meta_offset: meta_offset,
             block_cache,
             bloom: Some(bloom),
+            min_ts: self.min_ts,
             max_ts: self.max_ts,
         })
     }
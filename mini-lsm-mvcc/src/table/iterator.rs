@@ -1,17 +1,32 @@
+use std::ops::Bound;
 use std::sync::Arc;
 
 use anyhow::Result;
+use bytes::Bytes;
 
 use super::SsTable;
 use crate::block::BlockIterator;
 use crate::iterators::StorageIterator;
 use crate::key::KeySlice;
 
+/// Check if a user key does not go beyond the upper bound.
+pub(crate) fn key_within_upper_bound(key: &[u8], upper: Bound<&[u8]>) -> bool {
+    match upper {
+        Bound::Included(upper) => key <= upper,
+        Bound::Excluded(upper) => key < upper,
+        Bound::Unbounded => true,
+    }
+}
+
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    /// The iterator stops at this bound, and blocks that start after it are never read.
+    upper: Bound<Bytes>,
+    /// Whether the iterator has gone beyond `upper`.
+    past_upper: bool,
 }
 
 impl SsTableIterator {
@@ -29,6 +44,8 @@ impl SsTableIterator {
             blk_iter,
             table,
             blk_idx,
+            upper: Bound::Unbounded,
+            past_upper: false,
         };
         Ok(iter)
     }
@@ -38,6 +55,7 @@ impl SsTableIterator {
         let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table)?;
         self.blk_idx = blk_idx;
         self.blk_iter = blk_iter;
+        self.check_upper_bound();
         Ok(())
     }
 
@@ -62,15 +80,57 @@ impl SsTableIterator {
             blk_iter,
             table,
             blk_idx,
+            upper: Bound::Unbounded,
+            past_upper: false,
         };
         Ok(iter)
     }
 
     /// Seek to the first key-value pair which >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
-        self.blk_iter = blk_iter;
+        let blk_idx = self.table.find_block_idx(key);
+        self.blk_iter =
+            BlockIterator::create_and_seek_to_key(self.table.read_block_cached(blk_idx)?, key);
+        self.blk_idx = blk_idx;
+        self.past_upper = false;
+        if !self.blk_iter.is_valid() {
+            self.move_to_block(blk_idx + 1)?;
+        }
+        self.check_upper_bound();
+        Ok(())
+    }
+
+    /// Stop the iterator at the upper bound. Blocks that start after the bound will not be read.
+    pub fn with_upper_bound(mut self, upper: Bound<&[u8]>) -> Self {
+        self.set_upper_bound(upper);
+        self
+    }
+
+    pub(crate) fn set_upper_bound(&mut self, upper: Bound<&[u8]>) {
+        self.upper = upper.map(Bytes::copy_from_slice);
+        self.check_upper_bound();
+    }
+
+    fn within_upper_bound(&self, key: &[u8]) -> bool {
+        key_within_upper_bound(key, self.upper.as_ref().map(|x| x.as_ref()))
+    }
+
+    fn check_upper_bound(&mut self) {
+        self.past_upper =
+            self.blk_iter.is_valid() && !self.within_upper_bound(self.blk_iter.key().key_ref());
+    }
+
+    /// Move to the first entry of the `blk_idx`-th block, unless the block starts beyond the upper bound.
+    fn move_to_block(&mut self, blk_idx: usize) -> Result<()> {
         self.blk_idx = blk_idx;
+        if blk_idx < self.table.num_of_blocks() {
+            if !self.within_upper_bound(self.table.block_meta[blk_idx].first_key.key_ref()) {
+                self.past_upper = true;
+                return Ok(());
+            }
+            self.blk_iter =
+                BlockIterator::create_and_seek_to_first(self.table.read_block_cached(blk_idx)?);
+        }
         Ok(())
     }
 }
@@ -87,19 +147,21 @@ impl StorageIterator for SsTableIterator {
     }
 
     fn is_valid(&self) -> bool {
-        self.blk_iter.is_valid()
+        !self.past_upper && self.blk_iter.is_valid()
     }
 
     fn next(&mut self) -> Result<()> {
         self.blk_iter.next();
         if !self.blk_iter.is_valid() {
-            self.blk_idx += 1;
-            if self.blk_idx < self.table.num_of_blocks() {
-                self.blk_iter = BlockIterator::create_and_seek_to_first(
-                    self.table.read_block_cached(self.blk_idx)?,
-                );
-            }
+            self.move_to_block(self.blk_idx + 1)?;
+        }
+        if !self.past_upper {
+            self.check_upper_bound();
         }
         Ok(())
     }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_to_key(key)
+    }
 }
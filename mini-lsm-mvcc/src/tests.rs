@@ -1,4 +1,28 @@
+mod backup;
+mod bulk_load;
+mod changes;
+mod checkpoint;
+mod gc;
+mod group_commit;
 mod harness;
+mod ingest;
+mod isolation;
+mod iterator_seek;
+mod manifest_format;
+mod manifest_rotation;
+mod multi_get;
+mod pessimistic;
+mod prefix_scan;
+mod savepoint;
+mod serializable_conflicts;
+mod shared_wal;
+mod snapshot;
+mod tailing;
+mod time_travel;
+mod txn_spill;
+mod wal_batch;
+mod wal_recovery;
+mod watch;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -19,3 +43,4 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+mod write_options;
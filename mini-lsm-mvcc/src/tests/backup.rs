@@ -0,0 +1,153 @@
+use std::{os::unix::fs::FileExt, path::Path};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    backup::BackupEngine,
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn backup_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
+
+fn shared_files(dir: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(dir.join("shared"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_incremental_backup() {
+    let dir = tempdir().unwrap();
+    let db_dir = dir.path().join("db");
+    let backup_dir = dir.path().join("backup");
+    let storage = MiniLsm::open(&db_dir, backup_options()).unwrap();
+    let engine = BackupEngine::open(&backup_dir).unwrap();
+
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let first = engine.create_backup(&storage).unwrap();
+    assert_eq!(first.backup_id, 1);
+    assert_eq!(shared_files(&backup_dir).len(), 1);
+
+    storage.put(b"a", b"2").unwrap();
+    storage.force_flush().unwrap();
+    let second = engine.create_backup(&storage).unwrap();
+    assert_eq!(second.backup_id, 2);
+    assert!(second.commit_ts > first.commit_ts);
+    // the SST of the first backup is shared
+    assert_eq!(shared_files(&backup_dir).len(), 2);
+    assert_eq!(
+        second
+            .files
+            .iter()
+            .filter(|f| f.path.starts_with("shared/"))
+            .count(),
+        2
+    );
+    assert_eq!(engine.list_backups().unwrap(), vec![first, second]);
+    engine.verify(1).unwrap();
+    engine.verify(2).unwrap();
+
+    let restore_dir = dir.path().join("restore1");
+    engine.restore(1, &restore_dir).unwrap();
+    let restored = MiniLsm::open(&restore_dir, backup_options()).unwrap();
+    assert_eq!(restored.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(restored.get(b"b").unwrap(), Some(Bytes::from("1")));
+    assert!(engine.restore(2, &restore_dir).is_err());
+
+    // deleting the first backup keeps the SSTs of the second one
+    engine.purge_old_backups(1).unwrap();
+    assert_eq!(engine.list_backups().unwrap().len(), 1);
+    assert!(engine.verify(1).is_err());
+    assert_eq!(shared_files(&backup_dir).len(), 2);
+    engine.verify(2).unwrap();
+    let restore_dir = dir.path().join("restore2");
+    engine.restore(2, &restore_dir).unwrap();
+    let restored = MiniLsm::open(&restore_dir, backup_options()).unwrap();
+    assert_eq!(restored.get(b"a").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(restored.get(b"b").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_backup_verify_corruption() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("db"), backup_options()).unwrap();
+    let backup_dir = dir.path().join("backup");
+    let engine = BackupEngine::open(&backup_dir).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    engine.create_backup(&storage).unwrap();
+    engine.verify(1).unwrap();
+
+    let sst = backup_dir
+        .join("shared")
+        .join(&shared_files(&backup_dir)[0]);
+    let file = std::fs::OpenOptions::new().write(true).open(sst).unwrap();
+    file.write_all_at(b"x", 0).unwrap();
+    assert!(engine.verify(1).is_err());
+    assert!(engine.restore(1, dir.path().join("restore")).is_err());
+    // the database is not affected
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_backup_without_wal() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(dir.path().join("db"), options.clone()).unwrap();
+    let backup_dir = dir.path().join("backup");
+    let engine = BackupEngine::open(&backup_dir).unwrap();
+
+    // the memtable is written to an SST of the backup, which is not the SST it is flushed to later
+    storage.put(b"a", b"1").unwrap();
+    engine.create_backup(&storage).unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"1").unwrap();
+    engine.create_backup(&storage).unwrap();
+    engine.verify(1).unwrap();
+    engine.verify(2).unwrap();
+
+    let restore_dir = dir.path().join("restore");
+    engine.restore(2, &restore_dir).unwrap();
+    let restored = MiniLsm::open(&restore_dir, options.clone()).unwrap();
+    assert_eq!(restored.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(restored.get(b"b").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(restored.get(b"c").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_failed_backup_is_cleaned_up() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(dir.path().join("db"), options.clone()).unwrap();
+    let backup_dir = dir.path().join("backup");
+    let engine = BackupEngine::open(&backup_dir).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    engine.create_backup(&storage).unwrap();
+
+    // another database has an SST of the same id
+    let other = MiniLsm::open(dir.path().join("other"), options).unwrap();
+    other.put(b"a", b"other").unwrap();
+    other.force_flush().unwrap();
+    assert!(engine.create_backup(&other).is_err());
+    assert!(!backup_dir.join("tmp").exists());
+    assert!(!backup_dir.join("private").join("2").exists());
+
+    storage.put(b"b", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let info = engine.create_backup(&storage).unwrap();
+    assert_eq!(info.backup_id, 2);
+    engine.verify(2).unwrap();
+}
@@ -0,0 +1,105 @@
+use std::{path::Path, sync::atomic::Ordering};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn bulk_load_options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        },
+    ))
+}
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key{:05}", i).into_bytes()
+}
+
+fn num_ssts(dir: &Path) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "sst")
+        })
+        .count()
+}
+
+#[test]
+fn test_bulk_load() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, bulk_load_options()).unwrap();
+    let value = vec![b'v'; 1000];
+    let snapshot = storage.snapshot();
+
+    let mut bulk_load = storage.bulk_load();
+    bulk_load
+        .write_stream((0..1500).map(|i| (key_of(i), &value)))
+        .unwrap();
+    bulk_load
+        .write_stream((1500..3000).map(|i| (key_of(i), &value)))
+        .unwrap();
+    assert!(bulk_load.put(&key_of(0), &value).is_err());
+    assert!(storage.inner.compaction_pauses.load(Ordering::SeqCst) > 0);
+    let ts = bulk_load.finish().unwrap();
+    assert!(ts > snapshot.read_ts());
+    assert_eq!(storage.inner.compaction_pauses.load(Ordering::SeqCst), 0);
+
+    {
+        // split by `target_sst_size` into the bottom level
+        let state = storage.inner.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert!(state.levels[..3].iter().all(|(_, ids)| ids.is_empty()));
+        assert!(state.levels[3].1.len() >= 3);
+    }
+    assert_eq!(snapshot.get(&key_of(0)).unwrap(), None);
+    for i in [0, 1499, 1500, 2999] {
+        assert_eq!(storage.get(&key_of(i)).unwrap().unwrap(), value);
+    }
+    storage.put(&key_of(0), b"new").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, bulk_load_options()).unwrap();
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(Bytes::from("new")));
+    assert_eq!(storage.get(&key_of(2999)).unwrap().unwrap(), value);
+}
+
+#[test]
+fn test_bulk_load_abort() {
+    let dir = tempdir().unwrap();
+    let mut options = bulk_load_options();
+    options.compaction_options = CompactionOptions::NoCompaction;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let value = vec![b'v'; 1000];
+    let mut bulk_load = storage.bulk_load();
+    bulk_load
+        .write_stream((0..2000).map(|i| (key_of(i), &value)))
+        .unwrap();
+    drop(bulk_load);
+    assert_eq!(num_ssts(dir.path()), 0);
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+
+    // the bottom level is a sorted run
+    let mut bulk_load = storage.bulk_load();
+    bulk_load.put(&key_of(0), b"1").unwrap();
+    bulk_load.finish().unwrap();
+    let mut bulk_load = storage.bulk_load();
+    bulk_load.put(&key_of(0), b"2").unwrap();
+    assert!(bulk_load.finish().is_err());
+    assert_eq!(num_ssts(dir.path()), 1);
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(Bytes::from("1")));
+    storage.put(&key_of(1), b"1").unwrap();
+}
@@ -0,0 +1,120 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{HistoryRetention, LsmStorageOptions, MiniLsm},
+    mvcc::changes::ChangeIterator,
+};
+
+fn collect_changes(mut iter: ChangeIterator) -> Vec<(Bytes, u64, Option<Bytes>)> {
+    let mut changes = Vec::new();
+    while iter.is_valid() {
+        let value = if iter.is_tombstone() {
+            None
+        } else {
+            Some(Bytes::copy_from_slice(iter.value()))
+        };
+        changes.push((Bytes::copy_from_slice(iter.key()), iter.commit_ts(), value));
+        iter.next().unwrap();
+    }
+    changes
+}
+
+#[test]
+fn test_changes_between() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap(); // ts 1
+    storage.put(b"b", b"1").unwrap(); // ts 2
+    storage.force_flush().unwrap();
+    storage.put(b"a", b"2").unwrap(); // ts 3
+    storage.delete(b"b").unwrap(); // ts 4
+    storage.force_flush().unwrap();
+    storage.put(b"c", b"3").unwrap(); // ts 5
+    storage.put(b"a", b"3").unwrap(); // ts 6
+
+    let iter = storage
+        .changes_between(0, 6, Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(
+        collect_changes(iter),
+        vec![
+            (Bytes::from("a"), 6, Some(Bytes::from("3"))),
+            (Bytes::from("a"), 3, Some(Bytes::from("2"))),
+            (Bytes::from("a"), 1, Some(Bytes::from("1"))),
+            (Bytes::from("b"), 4, None),
+            (Bytes::from("b"), 2, Some(Bytes::from("1"))),
+            (Bytes::from("c"), 5, Some(Bytes::from("3"))),
+        ]
+    );
+
+    let iter = storage
+        .changes_between(2, 5, Bound::Unbounded, Bound::Excluded(b"c"))
+        .unwrap();
+    assert_eq!(
+        collect_changes(iter),
+        vec![
+            (Bytes::from("a"), 3, Some(Bytes::from("2"))),
+            (Bytes::from("b"), 4, None),
+        ]
+    );
+
+    // the iterator pins `from_ts`, so compaction keeps the versions in the window
+    let iter = storage
+        .changes_between(3, 100, Bound::Included(b"b"), Bound::Unbounded)
+        .unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(
+        collect_changes(iter),
+        vec![
+            (Bytes::from("b"), 4, None),
+            (Bytes::from("c"), 5, Some(Bytes::from("3"))),
+        ]
+    );
+    let iter = storage
+        .changes_between(3, 100, Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(
+        collect_changes(iter),
+        vec![
+            (Bytes::from("a"), 6, Some(Bytes::from("3"))),
+            (Bytes::from("b"), 4, None),
+            (Bytes::from("c"), 5, Some(Bytes::from("3"))),
+        ]
+    );
+
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.min_readable_ts(), 6);
+    assert!(storage
+        .changes_between(3, 100, Bound::Unbounded, Bound::Unbounded)
+        .is_err());
+}
+
+#[test]
+fn test_changes_between_with_retention() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.history_retention = Some(HistoryRetention::Commits(2));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 1..=5 {
+        storage.put(b"a", format!("{}", i).as_bytes()).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.min_readable_ts(), 3);
+    let iter = storage
+        .changes_between(3, 5, Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(
+        collect_changes(iter),
+        vec![
+            (Bytes::from("a"), 5, Some(Bytes::from("5"))),
+            (Bytes::from("a"), 4, Some(Bytes::from("4"))),
+        ]
+    );
+}
@@ -0,0 +1,112 @@
+use std::os::unix::fs::MetadataExt;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, TieredCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteOptions},
+};
+
+fn check_checkpoint(options: LsmStorageOptions) {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..3 {
+        storage
+            .put(format!("key{}", i).as_bytes(), b"flushed")
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.put(b"key0", b"memtable").unwrap();
+    storage.delete(b"key1").unwrap();
+
+    let checkpoint_dir = dir.path().join("checkpoint");
+    storage.checkpoint(&checkpoint_dir).unwrap();
+    assert!(storage.checkpoint(&checkpoint_dir).is_err());
+    // the flushed SSTs are shared with the database
+    let shared = std::fs::read_dir(&checkpoint_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sst"))
+        .filter(|path| std::fs::metadata(path).unwrap().nlink() == 2)
+        .count();
+    assert_eq!(shared, 3);
+
+    // writes after the checkpoint are not in it
+    storage.put(b"key2", b"after").unwrap();
+    storage.force_flush().unwrap();
+
+    let checkpoint = MiniLsm::open(&checkpoint_dir, options.clone()).unwrap();
+    assert_eq!(
+        checkpoint.get(b"key0").unwrap(),
+        Some(Bytes::from("memtable"))
+    );
+    assert_eq!(checkpoint.get(b"key1").unwrap(), None);
+    assert_eq!(
+        checkpoint.get(b"key2").unwrap(),
+        Some(Bytes::from("flushed"))
+    );
+    checkpoint.put(b"key3", b"checkpoint").unwrap();
+    checkpoint.force_flush().unwrap();
+    checkpoint.close().unwrap();
+    drop(checkpoint);
+
+    let checkpoint = MiniLsm::open(&checkpoint_dir, options).unwrap();
+    assert_eq!(
+        checkpoint.get(b"key3").unwrap(),
+        Some(Bytes::from("checkpoint"))
+    );
+    assert_eq!(storage.get(b"key3").unwrap(), None);
+    assert_eq!(storage.get(b"key2").unwrap(), Some(Bytes::from("after")));
+}
+
+#[test]
+fn test_checkpoint_with_wal() {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    check_checkpoint(options);
+}
+
+#[test]
+fn test_checkpoint_without_wal() {
+    check_checkpoint(LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 10,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }),
+    ));
+}
+
+#[test]
+fn test_checkpoint_with_unlogged_writes() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let no_wal = WriteOptions {
+        sync: false,
+        disable_wal: true,
+    };
+    storage.put(b"a", b"1").unwrap();
+    storage.put_with_options(b"b", b"1", no_wal).unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.delete_with_options(b"a", no_wal).unwrap();
+
+    // the writes skipping the WAL are in the checkpoint all the same
+    let checkpoint_dir = dir.path().join("checkpoint");
+    storage.checkpoint(&checkpoint_dir).unwrap();
+    let checkpoint = MiniLsm::open(&checkpoint_dir, options.clone()).unwrap();
+    assert_eq!(checkpoint.get(b"a").unwrap(), None);
+    assert_eq!(checkpoint.get(b"b").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(checkpoint.get(b"c").unwrap(), Some(Bytes::from("1")));
+    checkpoint.put(b"d", b"1").unwrap();
+    checkpoint.close().unwrap();
+    drop(checkpoint);
+
+    // and writes to the checkpoint are recovered from its own WAL
+    let checkpoint = MiniLsm::open(&checkpoint_dir, options).unwrap();
+    assert_eq!(checkpoint.get(b"b").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(checkpoint.get(b"d").unwrap(), Some(Bytes::from("1")));
+}
@@ -0,0 +1,145 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn gc_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.gc_interval = None;
+    options
+}
+
+#[test]
+fn test_gc_on_open() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, gc_options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let input = storage.inner.state.read().l0_sstables[0];
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // leftovers of a crash: an input of a compaction, a WAL segment, a spilled transaction and an unfinished
+    // manifest rotation
+    let orphans = [
+        format!("{:05}.sst", input),
+        "00042.wal".to_string(),
+        "00003.spill".to_string(),
+        "MANIFEST-000042".to_string(),
+        "CURRENT.tmp".to_string(),
+    ];
+    for name in &orphans {
+        std::fs::write(dir.path().join(name), b"orphan").unwrap();
+    }
+    // an SST with an id not recorded as allocated, which may be written by a flush or compaction
+    std::fs::write(dir.path().join("00042.sst"), b"orphan").unwrap();
+    let storage = MiniLsm::open(&dir, gc_options()).unwrap();
+    for name in &orphans {
+        assert!(!dir.path().join(name).exists(), "{} not removed", name);
+    }
+    assert!(dir.path().join("00042.sst").exists());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+
+    // live files are kept
+    std::fs::write(dir.path().join("00001.sst.bak"), b"not ours").unwrap();
+    let report = storage.collect_garbage().unwrap();
+    assert!(report.removed_files.is_empty());
+    assert!(dir.path().join("00001.sst.bak").exists());
+    assert!(dir.path().join("00042.sst").exists());
+
+    // the SST is removed once its id is allocated
+    while storage.inner.next_sst_id() <= 42 {}
+    let report = storage.collect_garbage().unwrap();
+    assert_eq!(report.removed_files, vec!["00042.sst"]);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_gc_on_open_without_manifest() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, gc_options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // SSTs whose manifest is lost are never collected as garbage of a new directory
+    let ssts = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sst"))
+        .collect::<Vec<_>>();
+    assert!(!ssts.is_empty());
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    assert!(MiniLsm::open(&dir, gc_options()).is_err());
+    for sst in ssts {
+        assert!(sst.exists());
+    }
+}
+
+#[test]
+fn test_gc_after_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, gc_options()).unwrap();
+    for i in 0..4 {
+        storage
+            .put(b"key", format!("value{}", i).as_bytes())
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    let report = storage.collect_garbage().unwrap();
+    assert!(report.removed_files.is_empty());
+    // an input of the compaction left behind by a crash
+    std::fs::write(dir.path().join("00001.sst"), b"orphan").unwrap();
+    let report = storage.collect_garbage().unwrap();
+    assert_eq!(report.removed_files, vec!["00001.sst"]);
+    assert_eq!(report.reclaimed_bytes, 6);
+    let ssts = std::fs::read_dir(dir.path())
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "sst")
+        })
+        .count();
+    assert_eq!(ssts, 1);
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("value3")));
+}
+
+#[test]
+fn test_gc_without_wal_keeps_wal_segments() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, gc_options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let mut options = gc_options();
+    options.enable_wal = false;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let report = storage.collect_garbage().unwrap();
+    assert!(report
+        .removed_files
+        .iter()
+        .all(|name| !name.ends_with(".wal")));
+    storage.close().unwrap();
+    drop(storage);
+
+    // the unflushed write is recovered once the WAL is enabled again
+    let storage = MiniLsm::open(&dir, gc_options()).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
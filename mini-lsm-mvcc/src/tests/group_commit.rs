@@ -0,0 +1,166 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord, WriteOptions},
+    mvcc::txn::{IsolationLevel, TxnOptions},
+};
+
+fn wait_for_queued(storage: &MiniLsm, num_queued: usize) {
+    while storage.inner.mvcc().commit_queue.for_testing_num_queued() < num_queued {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_group_commit_concurrent_sync_writes() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let sync = WriteOptions {
+        sync: true,
+        disable_wal: false,
+    };
+    let initial_ts = storage.inner.mvcc().latest_commit_ts();
+    let handles = (0..8)
+        .map(|thread| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    storage
+                        .put_with_options(
+                            format!("{}-{}", thread, i).as_bytes(),
+                            format!("{}", i).as_bytes(),
+                            sync,
+                        )
+                        .unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    // every write gets its own commit ts
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), initial_ts + 400);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for thread in 0..8 {
+        for i in 0..50 {
+            assert_eq!(
+                storage.get(format!("{}-{}", thread, i).as_bytes()).unwrap(),
+                Some(Bytes::from(format!("{}", i)))
+            );
+        }
+    }
+}
+
+#[test]
+fn test_group_commit_serializable_conflict_in_group() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    // hold back the first leader, so that the next requests are committed as one group
+    let commit_lock = storage.inner.mvcc().commit_lock.lock();
+    let leader = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"x", b"1"))
+    };
+    wait_for_queued(&storage, 1);
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.get(b"a").unwrap();
+    txn1.put(b"b", b"1");
+    txn2.put(b"a", b"2");
+    let commit = |txn: &Arc<crate::mvcc::txn::Transaction>| {
+        let txn = txn.clone();
+        std::thread::spawn(move || txn.commit())
+    };
+    let txn2_commit = commit(&txn2);
+    wait_for_queued(&storage, 2);
+    let txn1_commit = commit(&txn1);
+    wait_for_queued(&storage, 3);
+    drop(commit_lock);
+
+    leader.join().unwrap().unwrap();
+    txn2_commit.join().unwrap().unwrap();
+    // txn1 read a key written by txn2, which committed earlier in the same group
+    assert!(txn1_commit.join().unwrap().is_err());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"x").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_group_commit_rejects_empty_key_or_value() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let initial_ts = storage.inner.mvcc().latest_commit_ts();
+    assert!(storage.put(b"a", b"").is_err());
+    assert!(storage.put(b"", b"1").is_err());
+    assert!(storage.delete(b"").is_err());
+    assert!(storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"a"[..], &b"1"[..]),
+            WriteBatchRecord::Put(b"b", b"")
+        ])
+        .is_err());
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), initial_ts);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    // later writers are not blocked by the failed ones
+    storage.put(b"a", b"1").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_group_commit_write_failure() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal_segment_size = 1;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let (live, _) = storage.inner.wal.as_ref().unwrap().for_testing_segments();
+    // the WAL fails to roll to the next segment
+    let blocked = dir
+        .path()
+        .join(format!("{:05}.wal", live.last().unwrap() + 1));
+    std::fs::create_dir(&blocked).unwrap();
+
+    // a running serializable transaction makes commits record their writes
+    let reader = storage
+        .new_txn_with_options(TxnOptions {
+            isolation: IsolationLevel::Serializable,
+            read_only: false,
+        })
+        .unwrap();
+    let initial_ts = storage.inner.mvcc().latest_commit_ts();
+    assert!(storage.put(b"a", b"1").is_err());
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), initial_ts + 1);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert!(storage
+        .inner
+        .mvcc()
+        .committed_txns
+        .lock()
+        .range(initial_ts + 1..)
+        .next()
+        .is_none());
+
+    // the WAL may hold the failed commit, so its timestamp is not reused
+    std::fs::remove_dir(&blocked).unwrap();
+    storage.put(b"b", b"1").unwrap();
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), initial_ts + 2);
+    reader.get(b"b").unwrap();
+    reader.put(b"c", b"1");
+    assert!(reader.commit().is_err());
+}
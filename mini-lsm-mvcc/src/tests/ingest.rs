@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, TieredCompactionOptions},
+    ingest::SstFileWriter,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn write_sst(path: PathBuf, prefix: &str, value: &[u8]) -> PathBuf {
+    let mut writer = SstFileWriter::create(&path, 4096);
+    for i in 0..100 {
+        writer
+            .put(format!("{}{:03}", prefix, i).as_bytes(), value)
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    path
+}
+
+fn external_dir(dir: &Path) -> PathBuf {
+    let path = dir.join("external");
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+#[test]
+fn test_sst_file_writer() {
+    let dir = tempdir().unwrap();
+    let mut writer = SstFileWriter::create(dir.path().join("1.sst"), 4096);
+    writer.put(b"b", b"1").unwrap();
+    assert!(writer.put(b"b", b"2").is_err());
+    assert!(writer.put(b"a", b"2").is_err());
+    assert!(writer.put(b"c", b"").is_err());
+    writer.delete(b"c").unwrap();
+    writer.finish().unwrap();
+    let writer = SstFileWriter::create(dir.path().join("2.sst"), 4096);
+    assert!(writer.finish().is_err());
+}
+
+#[test]
+fn test_ingest_levels() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(dir.path().join("db"), options.clone()).unwrap();
+    let external = external_dir(dir.path());
+    storage.put(b"a000", b"old").unwrap();
+    storage.put(b"b050", b"old").unwrap();
+    storage.force_flush().unwrap();
+    let snapshot = storage.snapshot();
+
+    // a file overlapping L0 goes to L0, and the others to the bottom level
+    let files = vec![
+        write_sst(external.join("b.sst"), "b", b"new"),
+        write_sst(external.join("c.sst"), "c", b"new"),
+    ];
+    let ts = storage.ingest(&files).unwrap();
+    assert!(ts > snapshot.read_ts());
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.l0_sstables.len(), 2);
+        assert_eq!(state.levels[0].1.len(), 1);
+        assert_eq!(state.sstables[&state.levels[0].1[0]].max_ts(), ts);
+    }
+    assert_eq!(storage.get(b"a000").unwrap(), Some(Bytes::from("old")));
+    assert_eq!(storage.get(b"b050").unwrap(), Some(Bytes::from("new")));
+    assert_eq!(storage.get(b"c099").unwrap(), Some(Bytes::from("new")));
+    assert_eq!(snapshot.get(b"b050").unwrap(), Some(Bytes::from("old")));
+    assert_eq!(snapshot.get(b"c000").unwrap(), None);
+
+    // the source files are not needed by the database
+    for file in &files {
+        std::fs::remove_file(file).unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(dir.path().join("db"), options).unwrap();
+    assert_eq!(storage.get(b"b050").unwrap(), Some(Bytes::from("new")));
+    assert_eq!(storage.get(b"c050").unwrap(), Some(Bytes::from("new")));
+    assert!(storage.snapshot().read_ts() >= ts);
+}
+
+#[test]
+fn test_ingest_invalid_files() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(dir.path().join("db"), options).unwrap();
+    let external = external_dir(dir.path());
+    let first = write_sst(external.join("1.sst"), "a", b"1");
+    let second = write_sst(external.join("2.sst"), "a", b"2");
+    assert!(storage.ingest(&[first, second]).is_err());
+    assert!(storage.ingest(&[]).is_err());
+
+    // an SST of a database has timestamps
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let sst = std::fs::read_dir(dir.path().join("db"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "sst"))
+        .unwrap();
+    assert!(storage.ingest(&[sst]).is_err());
+}
+
+#[test]
+fn test_ingest_tiered() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 10,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        },
+    ));
+    let storage = MiniLsm::open(dir.path().join("db"), options).unwrap();
+    let external = external_dir(dir.path());
+    storage.put(b"a000", b"old").unwrap();
+    storage.force_flush().unwrap();
+    let files = vec![
+        write_sst(external.join("a.sst"), "a", b"new"),
+        write_sst(external.join("b.sst"), "b", b"new"),
+    ];
+    storage.ingest(&files).unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.levels.len(), 2);
+        assert_eq!(state.levels[0].1.len(), 2);
+    }
+    assert_eq!(storage.get(b"a000").unwrap(), Some(Bytes::from("new")));
+    assert_eq!(storage.get(b"b099").unwrap(), Some(Bytes::from("new")));
+}
+
+#[test]
+fn test_ingest_flushes_overlapping_memtables() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(dir.path().join("db"), options).unwrap();
+    let external = external_dir(dir.path());
+    storage.put(b"z", b"old").unwrap();
+
+    // the memtables are kept if they do not overlap the files
+    storage
+        .ingest(&[write_sst(external.join("a.sst"), "a", b"new")])
+        .unwrap();
+    assert!(!storage.inner.state.read().memtable.is_empty());
+
+    // an older version in the memtables is flushed before the file is placed above it
+    storage.put(b"m050", b"old").unwrap();
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.put(b"y", b"old").unwrap();
+    let ts = storage
+        .ingest(&[write_sst(external.join("m.sst"), "m", b"new")])
+        .unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert!(state.memtable.is_empty());
+        assert!(state.imm_memtables.is_empty());
+        assert_eq!(state.l0_sstables.len(), 3);
+        assert_eq!(state.sstables[&state.l0_sstables[0]].max_ts(), ts);
+    }
+    assert_eq!(storage.get(b"m050").unwrap(), Some(Bytes::from("new")));
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(b"m050").unwrap(), Some(Bytes::from("new")));
+    assert_eq!(storage.get(b"z").unwrap(), Some(Bytes::from("old")));
+}
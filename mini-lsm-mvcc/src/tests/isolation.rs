@@ -0,0 +1,172 @@
+use std::{ops::Bound, sync::atomic::Ordering};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mvcc::txn::{IsolationLevel, TxnOptions},
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+fn txn_options(isolation: IsolationLevel) -> TxnOptions {
+    TxnOptions {
+        isolation,
+        read_only: false,
+    }
+}
+
+#[test]
+fn test_read_committed() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let rc_txn = storage
+        .new_txn_with_options(txn_options(IsolationLevel::ReadCommitted))
+        .unwrap();
+    let snapshot_txn = storage
+        .new_txn_with_options(txn_options(IsolationLevel::Snapshot))
+        .unwrap();
+    assert_eq!(rc_txn.get(b"a").unwrap(), Some(Bytes::from("1")));
+    storage.put(b"a", b"2").unwrap();
+    storage.put(b"b", b"2").unwrap();
+    assert_eq!(rc_txn.get(b"a").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(snapshot_txn.get(b"a").unwrap(), Some(Bytes::from("1")));
+    rc_txn.put(b"c", b"3");
+    check_lsm_iter_result_by_key(
+        &mut rc_txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("2")),
+            (Bytes::from("b"), Bytes::from("2")),
+            (Bytes::from("c"), Bytes::from("3")),
+        ],
+    );
+    rc_txn.commit().unwrap();
+    snapshot_txn.commit().unwrap();
+}
+
+#[test]
+fn test_per_txn_serializable() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    // conflicts with a non-serializable transaction and a write outside of a transaction are detected
+    let txn1 = storage
+        .new_txn_with_options(txn_options(IsolationLevel::Serializable))
+        .unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.get(b"a").unwrap();
+    txn1.put(b"b", b"1");
+    txn2.put(b"a", b"2");
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+    drop(txn1);
+
+    let txn1 = storage
+        .new_txn_with_options(txn_options(IsolationLevel::Serializable))
+        .unwrap();
+    txn1.get(b"a").unwrap();
+    txn1.put(b"b", b"1");
+    storage.put(b"a", b"3").unwrap();
+    assert!(txn1.commit().is_err());
+    drop(txn1);
+
+    // the same workload commits under snapshot isolation
+    let txn1 = storage.new_txn().unwrap();
+    txn1.get(b"a").unwrap();
+    txn1.put(b"b", b"1");
+    storage.put(b"a", b"4").unwrap();
+    txn1.commit().unwrap();
+
+    // commits are only recorded while serializable transactions are running
+    assert_eq!(
+        storage
+            .inner
+            .mvcc()
+            .active_serializable_txns
+            .load(Ordering::SeqCst),
+        0
+    );
+    storage.put(b"a", b"5").unwrap();
+    assert!(!storage
+        .inner
+        .mvcc()
+        .committed_txns
+        .lock()
+        .contains_key(&storage.inner.mvcc().latest_commit_ts()));
+}
+
+#[test]
+fn test_read_only_txn() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let txn = storage
+        .new_txn_with_options(TxnOptions {
+            isolation: IsolationLevel::Serializable,
+            read_only: true,
+        })
+        .unwrap();
+    assert!(txn.conflict_set.is_none());
+
+    // a serializable transaction that only reads is not recorded for conflict checks
+    let reader = storage.new_txn().unwrap();
+    assert_eq!(reader.get(b"a").unwrap(), Some(Bytes::from("1")));
+    let writer = storage.new_txn().unwrap();
+    writer.put(b"b", b"1");
+    reader.commit().unwrap();
+    writer.commit().unwrap();
+    let mvcc = storage.inner.mvcc();
+    let committed_txns = mvcc.committed_txns.lock();
+    assert!(committed_txns.contains_key(&mvcc.latest_commit_ts()));
+    assert!(!committed_txns.contains_key(&(mvcc.latest_commit_ts() - 1)));
+    drop(committed_txns);
+    drop(reader);
+    drop(writer);
+
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert!(txn.try_put(b"a", b"2").is_err());
+    txn.delete(b"a");
+    assert!(txn.commit().is_err());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+
+    // single reads outside of a transaction do not track conflicts either
+    let _iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!(
+        storage
+            .inner
+            .mvcc()
+            .active_serializable_txns
+            .load(Ordering::SeqCst),
+        0
+    );
+}
+
+#[test]
+fn test_read_only_detected_on_commit() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from("1")));
+    txn.set_savepoint();
+    txn.put(b"b", b"1");
+    txn.rollback_to_savepoint().unwrap();
+    storage.put(b"a", b"2").unwrap();
+
+    // the transaction wrote nothing in the end, so what it read cannot conflict
+    txn.commit().unwrap();
+    let mvcc = storage.inner.mvcc();
+    // and other commits need not be recorded for it anymore
+    assert_eq!(mvcc.active_serializable_txns.load(Ordering::SeqCst), 0);
+    drop(txn);
+    assert_eq!(mvcc.active_serializable_txns.load(Ordering::SeqCst), 0);
+}
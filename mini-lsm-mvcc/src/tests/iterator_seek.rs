@@ -0,0 +1,162 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{merge_iterator::MergeIterator, StorageIterator},
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    tests::harness::{check_iter_result_by_key, check_lsm_iter_result_by_key, MockIterator},
+};
+
+#[test]
+fn test_merge_iterator_seek_revives_exhausted() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("1.3")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("d"), Bytes::from("2.4")),
+    ]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2)]);
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("1.1")),
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("1.3")),
+            (Bytes::from("d"), Bytes::from("2.4")),
+        ],
+    );
+    iter.seek(KeySlice::for_testing_from_slice_no_ts(b"b"))
+        .unwrap();
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("1.3")),
+            (Bytes::from("d"), Bytes::from("2.4")),
+        ],
+    );
+}
+
+#[test]
+fn test_merge_iterator_seek_error() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("1.3")),
+    ]);
+    let i2 = MockIterator::new_with_error(
+        vec![
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("d"), Bytes::from("2.4")),
+        ],
+        1,
+    );
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2)]);
+    assert!(iter
+        .seek(KeySlice::for_testing_from_slice_no_ts(b"c"))
+        .is_err());
+    assert!(!iter.is_valid());
+    // the iterator can be re-seeked after an error
+    iter.seek(KeySlice::for_testing_from_slice_no_ts(b"a"))
+        .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"a");
+}
+
+#[test]
+fn test_storage_iterator_seek() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let key = |i: usize| format!("key{:03}", i);
+    let value = |i: usize, v: usize| format!("value{}_{}", i, v);
+    // L1
+    for i in 0..100 {
+        storage
+            .put(key(i).as_bytes(), value(i, 1).as_bytes())
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    // L0
+    for i in (0..100).step_by(3) {
+        storage
+            .put(key(i).as_bytes(), value(i, 2).as_bytes())
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    // memtable
+    for i in (0..100).step_by(5) {
+        storage.delete(key(i).as_bytes()).unwrap();
+    }
+
+    let expected = |from: usize, to: usize| {
+        (from..to)
+            .filter(|i| i % 5 != 0)
+            .map(|i| {
+                let v = if i % 3 == 0 { 2 } else { 1 };
+                (Bytes::from(key(i)), Bytes::from(value(i, v)))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    check_lsm_iter_result_by_key(&mut iter, expected(0, 100));
+    for (from, seek_key) in [(50, key(50)), (11, key(11)), (98, key(98))] {
+        iter.seek(seek_key.as_bytes()).unwrap();
+        check_lsm_iter_result_by_key(&mut iter, expected(from, 100));
+    }
+    // seek between keys
+    iter.seek(b"key0305").unwrap();
+    check_lsm_iter_result_by_key(&mut iter, expected(31, 100));
+    iter.seek(b"key999").unwrap();
+    assert!(!iter.is_valid());
+
+    // seek should respect the upper bound of the scan
+    let mut iter = storage
+        .scan(Bound::Unbounded, Bound::Excluded(key(40).as_bytes()))
+        .unwrap();
+    iter.seek(key(30).as_bytes()).unwrap();
+    check_lsm_iter_result_by_key(&mut iter, expected(30, 40));
+    iter.seek(key(60).as_bytes()).unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_txn_iterator_seek() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.put(b"e", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"b", b"2");
+    txn.put(b"d", b"2");
+    txn.delete(b"c");
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    iter.seek(b"b").unwrap();
+    check_lsm_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("b"), Bytes::from("2")),
+            (Bytes::from("d"), Bytes::from("2")),
+            (Bytes::from("e"), Bytes::from("1")),
+        ],
+    );
+    iter.seek(b"c").unwrap();
+    check_lsm_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("d"), Bytes::from("2")),
+            (Bytes::from("e"), Bytes::from("1")),
+        ],
+    );
+    iter.seek(b"a").unwrap();
+    assert_eq!(iter.key(), b"a");
+}
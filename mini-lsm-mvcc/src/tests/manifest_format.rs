@@ -0,0 +1,131 @@
+use std::os::unix::fs::FileExt;
+
+use bytes::{BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions},
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    manifest::{FileMeta, LevelId, VersionEdit},
+};
+
+fn file_meta(id: usize) -> FileMeta {
+    FileMeta {
+        id,
+        first_key: KeyBytes::from_bytes_with_ts(Bytes::from("a"), 3),
+        last_key: KeyBytes::from_bytes_with_ts(Bytes::from("z"), 1),
+        size: 4096,
+        min_ts: 1,
+        max_ts: 3,
+    }
+}
+
+#[test]
+fn test_version_edit_encoding() {
+    let edit = VersionEdit {
+        level_ids: Some(vec![7, 3]),
+        deleted_files: vec![(LevelId::L0, 1), (LevelId::Level(3), 2)],
+        new_files: vec![
+            (LevelId::L0, file_meta(5)),
+            (LevelId::Level(7), file_meta(7)),
+        ],
+        next_sst_id: Some(8),
+        commit_ts: None,
+        flushed_lsn: Some(42),
+    };
+    let mut buf = Vec::new();
+    edit.encode(&mut buf);
+    assert_eq!(VersionEdit::decode(&buf).unwrap(), edit);
+
+    // fields added by later versions are skipped if they are marked as ignorable
+    let mut ignorable = buf.clone();
+    ignorable.put_u8(0x80 | 0x42);
+    ignorable.put_u32(3);
+    ignorable.put_slice(b"new");
+    assert_eq!(VersionEdit::decode(&ignorable).unwrap(), edit);
+    let mut unknown = buf.clone();
+    unknown.put_u8(0x42);
+    unknown.put_u32(0);
+    assert!(VersionEdit::decode(&unknown).is_err());
+    assert!(VersionEdit::decode(&buf[..buf.len() - 1]).is_err());
+}
+
+#[test]
+fn test_manifest_unsupported_version() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("MANIFEST-000001"))
+        .unwrap();
+    file.write_all_at(&2u16.to_be_bytes(), 4).unwrap();
+    assert!(MiniLsm::open(&dir, options).is_err());
+}
+
+fn check_reopen(options: LsmStorageOptions) {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..30 {
+        for j in 0..10 {
+            storage
+                .put(
+                    format!("key{:02}", j * 3 + i % 3).as_bytes(),
+                    format!("value{}", i).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    storage.close().unwrap();
+    let (l0_sstables, levels) = {
+        let state = storage.inner.state.read();
+        (state.l0_sstables.clone(), state.levels.clone())
+    };
+    assert!(levels.iter().any(|(_, files)| !files.is_empty()));
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.l0_sstables, l0_sstables);
+        assert_eq!(state.levels, levels);
+    }
+    for j in 0..30 {
+        assert_eq!(
+            storage.get(format!("key{:02}", j).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value{}", 27 + j % 3)))
+        );
+    }
+}
+
+#[test]
+fn test_manifest_recover_tiered() {
+    check_reopen(LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }),
+    ));
+}
+
+#[test]
+fn test_manifest_recover_leveled() {
+    check_reopen(LsmStorageOptions::default_for_week2_test(
+        CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        }),
+    ));
+}
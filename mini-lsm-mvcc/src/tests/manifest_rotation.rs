@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    manifest::LegacyManifestRecord,
+};
+
+fn manifest_files(dir: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("MANIFEST"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn current(dir: &Path) -> String {
+    std::fs::read_to_string(dir.join("CURRENT")).unwrap()
+}
+
+fn rotation_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.manifest_rotation_size = 512;
+    options
+}
+
+#[test]
+fn test_manifest_rotation() {
+    let dir = tempdir().unwrap();
+    let options = rotation_options();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(manifest_files(dir.path()), vec!["MANIFEST-000001"]);
+    assert_eq!(current(dir.path()), "MANIFEST-000001\n");
+    for i in 0..20 {
+        storage
+            .put(
+                format!("key{:02}", i % 5).as_bytes(),
+                format!("value{}", i).as_bytes(),
+            )
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    storage.close().unwrap();
+    // old manifests are removed once rotated
+    let files = manifest_files(dir.path());
+    assert_eq!(files.len(), 1);
+    assert_ne!(files[0], "MANIFEST-000001");
+    assert_eq!(current(dir.path()), format!("{}\n", files[0]));
+
+    let commit_ts = storage.inner.mvcc().latest_commit_ts();
+    let (l0_sstables, levels) = {
+        let state = storage.inner.state.read();
+        (state.l0_sstables.clone(), state.levels.clone())
+    };
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.l0_sstables, l0_sstables);
+        assert_eq!(state.levels, levels);
+    }
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), commit_ts);
+    for i in 15..20 {
+        assert_eq!(
+            storage.get(format!("key{:02}", i % 5).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value{}", i)))
+        );
+    }
+}
+
+#[test]
+fn test_manifest_rotation_interrupted() {
+    let dir = tempdir().unwrap();
+    let mut options = rotation_options();
+    options.compaction_options = CompactionOptions::NoCompaction;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // a rotation crashed before switching `CURRENT`
+    std::fs::write(dir.path().join("MANIFEST-000002"), b"garbage").unwrap();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    storage
+        .inner
+        .rotate_manifest(&storage.inner.state_lock.lock())
+        .unwrap();
+    assert_eq!(manifest_files(dir.path()), vec!["MANIFEST-000002"]);
+    storage.put(b"b", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_manifest_without_current() {
+    let dir = tempdir().unwrap();
+    let mut options = rotation_options();
+    options.compaction_options = CompactionOptions::NoCompaction;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    let sst_id = storage.inner.state.read().l0_sstables[0];
+    drop(storage);
+
+    // a manifest written before rotation was supported
+    let record = serde_json::to_vec(&LegacyManifestRecord::Flush(sst_id, 0)).unwrap();
+    let mut buf = (record.len() as u64).to_be_bytes().to_vec();
+    buf.extend_from_slice(&record);
+    buf.extend_from_slice(&crc32fast::hash(&record).to_be_bytes());
+    std::fs::write(dir.path().join("MANIFEST"), buf).unwrap();
+    std::fs::remove_file(dir.path().join("MANIFEST-000001")).unwrap();
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(manifest_files(dir.path()), vec!["MANIFEST-000001"]);
+    assert_eq!(current(dir.path()), "MANIFEST-000001\n");
+    // new writes are newer than the migrated ones
+    storage.put(b"a", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_data_files_without_manifest() {
+    let dir = tempdir().unwrap();
+    let mut options = rotation_options();
+    options.compaction_options = CompactionOptions::NoCompaction;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    let sst_id = storage.inner.state.read().l0_sstables[0];
+    drop(storage);
+
+    // the manifest is lost, which must not be mistaken for a new database
+    std::fs::remove_file(dir.path().join("MANIFEST-000001")).unwrap();
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    assert!(MiniLsm::open(&dir, options).is_err());
+    assert!(dir.path().join(format!("{:05}.sst", sst_id)).exists());
+}
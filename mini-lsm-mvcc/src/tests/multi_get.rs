@@ -0,0 +1,82 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+#[test]
+fn test_multi_get() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let key = |i: usize| format!("key{:04}", i);
+    for i in 0..1000 {
+        storage
+            .put(key(i).as_bytes(), format!("v1_{}", i).as_bytes())
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    for i in (0..1000).step_by(7) {
+        storage
+            .put(key(i).as_bytes(), format!("v2_{}", i).as_bytes())
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    for i in (0..1000).step_by(3) {
+        storage.delete(key(i).as_bytes()).unwrap();
+    }
+
+    let keys = (0..1200)
+        .rev()
+        .step_by(2)
+        .map(key)
+        .chain([key(10), key(10), "a".to_string(), "z".to_string()])
+        .collect::<Vec<_>>();
+    let keys = keys.iter().map(|x| x.as_bytes()).collect::<Vec<_>>();
+
+    let result = storage.multi_get(&keys).unwrap();
+    assert_eq!(result.len(), keys.len());
+    for (key, value) in keys.iter().zip(result) {
+        assert_eq!(
+            storage.get(key).unwrap(),
+            value,
+            "key {:?}",
+            Bytes::copy_from_slice(key)
+        );
+    }
+
+    // the snapshot does not see the deletions
+    let result = snapshot.multi_get(&keys).unwrap();
+    for (key, value) in keys.iter().zip(result) {
+        assert_eq!(snapshot.get(key).unwrap(), value);
+    }
+    assert_eq!(
+        snapshot.multi_get(&[b"key0000", b"key0001"]).unwrap(),
+        vec![Some(Bytes::from("v2_0")), Some(Bytes::from("v1_1"))]
+    );
+    assert_eq!(
+        storage.multi_get(&[b"key0000", b"key0001"]).unwrap(),
+        vec![None, Some(Bytes::from("v1_1"))]
+    );
+}
+
+#[test]
+fn test_txn_multi_get_local_writes() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"c", b"2");
+    txn.delete(b"a");
+    assert_eq!(
+        txn.multi_get(&[b"c", b"b", b"a", b"d"]).unwrap(),
+        vec![Some(Bytes::from("2")), Some(Bytes::from("1")), None, None]
+    );
+}
@@ -0,0 +1,93 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn open_pessimistic(dir: &tempfile::TempDir, lock_timeout: Duration) -> Arc<MiniLsm> {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.lock_timeout = Some(lock_timeout);
+    MiniLsm::open(dir, options).unwrap()
+}
+
+#[test]
+fn test_get_for_update_serializes_increments() {
+    let dir = tempdir().unwrap();
+    let storage = open_pessimistic(&dir, Duration::from_secs(10));
+    storage.put(b"counter", b"0").unwrap();
+    let handles = (0..8)
+        .map(|_| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    let txn = storage.new_txn().unwrap();
+                    let value = txn.get_for_update(b"counter").unwrap().unwrap();
+                    let value: u64 = std::str::from_utf8(&value).unwrap().parse().unwrap();
+                    txn.try_put(b"counter", format!("{}", value + 1).as_bytes())
+                        .unwrap();
+                    txn.commit().unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(storage.get(b"counter").unwrap(), Some(Bytes::from("160")));
+    assert_eq!(storage.inner.mvcc().lock_table.num_locked_keys(), 0);
+}
+
+#[test]
+fn test_lock_timeout() {
+    let dir = tempdir().unwrap();
+    let storage = open_pessimistic(&dir, Duration::from_millis(50));
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.put(b"a", b"1");
+    assert!(txn2.try_put(b"a", b"2").is_err());
+    assert!(txn2.get_for_update(b"a").is_err());
+    // an infallible put reports the lock failure on commit
+    txn2.put(b"b", b"2");
+    txn2.put(b"a", b"2");
+    assert!(txn2.commit().is_err());
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    txn1.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+
+    // locks are released on rollback and drop
+    let txn3 = storage.new_txn().unwrap();
+    txn3.put(b"a", b"3");
+    txn3.rollback();
+    let txn4 = storage.new_txn().unwrap();
+    txn4.try_delete(b"a").unwrap();
+    drop(txn4);
+    assert_eq!(storage.inner.mvcc().lock_table.num_locked_keys(), 0);
+    txn3.try_put(b"a", b"3").unwrap();
+    txn3.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("3")));
+}
+
+#[test]
+fn test_deadlock_detection() {
+    let dir = tempdir().unwrap();
+    let storage = open_pessimistic(&dir, Duration::from_secs(10));
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.try_put(b"a", b"1").unwrap();
+    txn2.try_put(b"b", b"2").unwrap();
+    let handle = {
+        let txn1 = txn1.clone();
+        std::thread::spawn(move || txn1.try_put(b"b", b"1"))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(txn2.try_put(b"a", b"2").is_err());
+    txn2.rollback();
+    handle.join().unwrap().unwrap();
+    txn1.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+}
@@ -0,0 +1,108 @@
+use std::{ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{concat_iterator::SstConcatIterator, StorageIterator},
+    key::KeySlice,
+    lsm_storage::{prefix_upper_bound, BlockCache, LsmStorageOptions, MiniLsm},
+    table::SsTableIterator,
+    tests::harness::{check_lsm_iter_result_by_key, generate_sst},
+};
+
+#[test]
+fn test_prefix_upper_bound() {
+    assert_eq!(prefix_upper_bound(b"abc"), Some(b"abd".to_vec()));
+    assert_eq!(prefix_upper_bound(b"ab\xff"), Some(b"ac".to_vec()));
+    assert_eq!(prefix_upper_bound(b"\xff\xff"), None);
+    assert_eq!(prefix_upper_bound(b""), None);
+}
+
+#[test]
+fn test_sst_iterator_upper_bound_pruning() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(BlockCache::new(1024));
+    let data = (0..100)
+        .map(|i| {
+            (
+                Bytes::from(format!("key_{:03}", i)),
+                Bytes::from(format!("value_{:03}", i)),
+            )
+        })
+        .collect::<Vec<_>>();
+    let sst = Arc::new(generate_sst(
+        1,
+        dir.path().join("1.sst"),
+        data,
+        Some(block_cache.clone()),
+    ));
+    assert!(sst.num_of_blocks() > 4);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone())
+        .unwrap()
+        .with_upper_bound(Bound::Excluded(b"key_010"));
+    let mut cnt = 0;
+    while iter.is_valid() {
+        cnt += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(cnt, 10);
+    let last_block = sst.find_block_idx(KeySlice::for_testing_from_slice_no_ts(b"key_009"));
+    for idx in 0..sst.num_of_blocks() {
+        assert_eq!(
+            block_cache.contains_key(&(1, idx)),
+            idx <= last_block,
+            "block {} should not be read",
+            idx
+        );
+    }
+
+    let mut iter = SstConcatIterator::create_and_seek_to_first(vec![sst])
+        .unwrap()
+        .with_upper_bound(Bound::Included(b"key_000"))
+        .unwrap();
+    assert_eq!(iter.key().key_ref(), b"key_000");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_prefix_scan() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for prefix in ["a", "b", "b\u{7f}", "c"] {
+        for i in 0..10 {
+            storage
+                .put(format!("{}{}", prefix, i).as_bytes(), b"1")
+                .unwrap();
+        }
+    }
+    storage.put(b"\xff\xff", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b5", b"2").unwrap();
+    storage.delete(b"b6").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b7", b"3").unwrap();
+
+    let mut expected = Vec::new();
+    for i in 0..10 {
+        let value = match i {
+            5 => "2",
+            6 => continue,
+            7 => "3",
+            _ => "1",
+        };
+        expected.push((Bytes::from(format!("b{}", i)), Bytes::from(value)));
+    }
+    for i in 0..10 {
+        expected.push((Bytes::from(format!("b\u{7f}{}", i)), Bytes::from("1")));
+    }
+    check_lsm_iter_result_by_key(&mut storage.prefix_scan(b"b").unwrap(), expected);
+    check_lsm_iter_result_by_key(
+        &mut storage.prefix_scan(b"\xff").unwrap(),
+        vec![(Bytes::from_static(b"\xff\xff"), Bytes::from("1"))],
+    );
+    check_lsm_iter_result_by_key(&mut storage.prefix_scan(b"d").unwrap(), vec![]);
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+#[test]
+fn test_txn_savepoint() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    assert!(txn.rollback_to_savepoint().is_err());
+    txn.put(b"b", b"1");
+    txn.set_savepoint();
+    txn.put(b"b", b"2");
+    txn.delete(b"a");
+    txn.set_savepoint();
+    txn.put(b"c", b"3");
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), None);
+    assert_eq!(txn.get(b"b").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(txn.get(b"c").unwrap(), None);
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(txn.get(b"b").unwrap(), Some(Bytes::from("1")));
+    assert!(txn.rollback_to_savepoint().is_err());
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"c").unwrap(), None);
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"d", b"1");
+    txn.set_savepoint();
+    txn.rollback();
+    assert_eq!(txn.get(b"d").unwrap(), None);
+    assert!(txn.rollback_to_savepoint().is_err());
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"d").unwrap(), None);
+}
+
+#[test]
+fn test_serializable_savepoint_restores_key_sets() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"b", b"1");
+    txn.set_savepoint();
+    // a failed sub-step reads `a`, then gets rolled back
+    txn.get(b"a").unwrap();
+    txn.rollback_to_savepoint().unwrap();
+    storage.put(b"a", b"2").unwrap();
+    txn.commit().unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"b", b"2");
+    txn.get(b"a").unwrap();
+    txn.set_savepoint();
+    txn.rollback_to_savepoint().unwrap();
+    storage.put(b"a", b"3").unwrap();
+    assert!(txn.commit().is_err());
+}
+
+#[test]
+fn test_savepoint_restores_write_error() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.lock_timeout = Some(Duration::from_millis(50));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let txn1 = storage.new_txn().unwrap();
+    txn1.put(b"a", b"1");
+
+    // a lock failure after the savepoint is rolled back with it
+    let txn2 = storage.new_txn().unwrap();
+    txn2.put(b"b", b"2");
+    txn2.set_savepoint();
+    txn2.put(b"a", b"2");
+    txn2.rollback_to_savepoint().unwrap();
+    txn2.commit().unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+
+    // a lock failure before the savepoint is kept
+    let txn3 = storage.new_txn().unwrap();
+    txn3.put(b"a", b"3");
+    txn3.set_savepoint();
+    txn3.put(b"c", b"3");
+    txn3.rollback_to_savepoint().unwrap();
+    assert!(txn3.commit().is_err());
+    txn1.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
@@ -0,0 +1,107 @@
+use std::{ops::Bound, sync::Arc};
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn open_serializable(dir: &tempfile::TempDir, conflict_tracking_limit: usize) -> Arc<MiniLsm> {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    options.conflict_tracking_limit = conflict_tracking_limit;
+    MiniLsm::open(dir, options).unwrap()
+}
+
+#[test]
+fn test_serializable_phantom() {
+    let dir = tempdir().unwrap();
+    let storage = open_serializable(&dir, 1 << 20);
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"e", b"1").unwrap();
+
+    // a scan that returned nothing still conflicts with an insert into its range
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    let iter = txn1
+        .scan(Bound::Excluded(b"a"), Bound::Excluded(b"e"))
+        .unwrap();
+    assert!(!iter.is_valid());
+    txn1.put(b"count", b"0");
+    txn2.put(b"c", b"1");
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+
+    // inserts outside of the scanned range do not conflict
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.scan(Bound::Excluded(b"a"), Bound::Excluded(b"e"))
+        .unwrap();
+    txn1.put(b"count", b"1");
+    txn2.put(b"e", b"2");
+    txn2.put(b"a", b"2");
+    txn2.commit().unwrap();
+    txn1.commit().unwrap();
+}
+
+#[test]
+fn test_serializable_exact_keys() {
+    let dir = tempdir().unwrap();
+    let storage = open_serializable(&dir, 1 << 20);
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.get(b"key1").unwrap();
+    txn1.put(b"key2", b"1");
+    txn2.put(b"key3", b"2");
+    txn2.commit().unwrap();
+    txn1.commit().unwrap();
+
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.get(b"key1").unwrap();
+    txn1.put(b"key2", b"1");
+    txn2.put(b"key1", b"2");
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+}
+
+#[test]
+fn test_serializable_tracking_limit() {
+    let dir = tempdir().unwrap();
+    let storage = open_serializable(&dir, 16);
+
+    // above the limit, keys are compared by hash
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    for i in 0..10 {
+        txn1.get(format!("key{}", i).as_bytes()).unwrap();
+    }
+    txn1.put(b"out", b"1");
+    txn2.put(b"other", b"2");
+    txn2.commit().unwrap();
+    txn1.commit().unwrap();
+
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    for i in 0..10 {
+        txn1.get(format!("key{}", i).as_bytes()).unwrap();
+    }
+    txn1.put(b"out", b"1");
+    txn2.put(b"key5", b"2");
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+
+    // scanned ranges are dropped above the limit, and conflict with any write
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.scan(Bound::Included(b"a"), Bound::Excluded(b"b"))
+        .unwrap();
+    for i in 0..10 {
+        txn1.put(format!("key{}", i).as_bytes(), b"1");
+    }
+    txn2.put(b"z", b"2");
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+}
@@ -0,0 +1,111 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn num_wal_files(dir: &Path) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "wal")
+        })
+        .count()
+}
+
+fn wal_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    // every entry below fills a segment
+    options.wal_segment_size = 64;
+    options
+}
+
+fn put_keys(storage: &MiniLsm, range: std::ops::Range<usize>) {
+    for i in range {
+        storage
+            .put(format!("key{:02}", i).as_bytes(), &[b'v'; 64])
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_shared_wal_recycle_segments() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    put_keys(&storage, 0..10);
+    let wal = storage.inner.wal.as_ref().unwrap();
+    let (live, recycled) = wal.for_testing_segments();
+    assert_eq!(live.len(), 11);
+    assert!(recycled.is_empty());
+
+    // freezing a memtable does not touch the WAL
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    assert_eq!(num_wal_files(dir.path()), 11);
+
+    // flushing releases the full segments, some of which are kept for reuse
+    storage.inner.force_flush_next_imm_memtable().unwrap();
+    let (live, recycled) = wal.for_testing_segments();
+    assert_eq!(live, vec![11]);
+    assert_eq!(recycled.len(), 4);
+    assert_eq!(num_wal_files(dir.path()), 5);
+
+    put_keys(&storage, 10..13);
+    let (live, recycled) = wal.for_testing_segments();
+    assert_eq!(live, vec![11, 12, 13, 14]);
+    assert_eq!(recycled.len(), 1);
+    assert_eq!(num_wal_files(dir.path()), 5);
+}
+
+#[test]
+fn test_shared_wal_replay_unflushed() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    put_keys(&storage, 0..5);
+    storage.force_flush().unwrap();
+    put_keys(&storage, 5..8);
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    put_keys(&storage, 8..10);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    {
+        let state = storage.inner.state.read();
+        // the unflushed entries of both memtables are replayed into one memtable
+        assert_eq!(state.imm_memtables.len(), 1);
+        assert_eq!(state.imm_memtables[0].map.len(), 5);
+        assert_eq!(state.imm_memtables[0].max_lsn(), 10);
+    }
+    for i in 0..10 {
+        assert_eq!(
+            storage.get(format!("key{:02}", i).as_bytes()).unwrap(),
+            Some(Bytes::copy_from_slice(&[b'v'; 64]))
+        );
+    }
+
+    // entries written after recovery are not mixed up with replayed ones
+    storage.put(b"key00", b"new").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    assert!(storage.inner.state.read().imm_memtables.is_empty());
+    assert_eq!(storage.get(b"key00").unwrap(), Some(Bytes::from("new")));
+}
@@ -0,0 +1,83 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mvcc::snapshot::Snapshot,
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+#[test]
+fn test_snapshot_read() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+    storage.force_flush().unwrap();
+
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(snapshot.get(b"c").unwrap(), None);
+    assert_eq!(
+        snapshot.multi_get(&[b"c", b"b", b"a"]).unwrap(),
+        vec![None, Some(Bytes::from("1")), Some(Bytes::from("1"))]
+    );
+    check_lsm_iter_result_by_key(
+        &mut snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("b"), Bytes::from("1")),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .snapshot()
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("2")),
+            (Bytes::from("c"), Bytes::from("2")),
+        ],
+    );
+}
+
+#[test]
+fn test_snapshot_watermark() {
+    fn assert_send_sync<T: Send + Sync + Clone>(_: &T) {}
+
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let snapshot: Snapshot = storage.snapshot();
+    assert_send_sync(&snapshot);
+    let read_ts = snapshot.read_ts();
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(storage.inner.mvcc().watermark(), read_ts);
+
+    let cloned = snapshot.clone();
+    drop(snapshot);
+    assert_eq!(storage.inner.mvcc().watermark(), read_ts);
+    let handle = std::thread::spawn(move || cloned.get(b"a").unwrap());
+    assert_eq!(handle.join().unwrap(), Some(Bytes::from("1")));
+
+    // the iterator keeps the snapshot alive
+    let iter = storage
+        .snapshot()
+        .scan(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(
+        storage.inner.mvcc().watermark(),
+        storage.inner.mvcc().latest_commit_ts()
+    );
+    assert_eq!(storage.inner.mvcc().ts.lock().1.num_retained_snapshots(), 1);
+    drop(iter);
+    assert_eq!(storage.inner.mvcc().ts.lock().1.num_retained_snapshots(), 0);
+}
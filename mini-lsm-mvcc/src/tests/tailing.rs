@@ -0,0 +1,117 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    ingest::SstFileWriter,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mvcc::tailing::TailingIterator,
+};
+
+fn drain(iter: &mut TailingIterator) -> Vec<(Bytes, Bytes)> {
+    let mut result = Vec::new();
+    while iter.poll().unwrap() {
+        result.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    result
+}
+
+#[test]
+fn test_tailing_iterator() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"log/001", b"1").unwrap();
+    storage.put(b"log/002", b"2").unwrap();
+    storage.force_flush().unwrap();
+    let mut iter = storage
+        .tailing_scan(Bound::Included(b"log/"), Bound::Excluded(b"log0"))
+        .unwrap();
+    assert_eq!(
+        drain(&mut iter),
+        vec![
+            (Bytes::from("log/001"), Bytes::from("1")),
+            (Bytes::from("log/002"), Bytes::from("2")),
+        ]
+    );
+    let read_ts = iter.read_ts();
+    assert!(!iter.poll().unwrap());
+    assert_eq!(iter.read_ts(), read_ts);
+
+    // new keys after the last position are observed, across a memtable freeze
+    storage.put(b"log/003", b"3").unwrap();
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    storage.put(b"log/004", b"4").unwrap();
+    // writes behind the last position and outside of the range are not
+    storage.put(b"log/000", b"0").unwrap();
+    storage.put(b"log/001", b"1.1").unwrap();
+    storage.put(b"log1", b"x").unwrap();
+    assert_eq!(
+        drain(&mut iter),
+        vec![
+            (Bytes::from("log/003"), Bytes::from("3")),
+            (Bytes::from("log/004"), Bytes::from("4")),
+        ]
+    );
+    assert!(iter.read_ts() > read_ts);
+
+    storage.delete(b"log/005").unwrap();
+    storage.put(b"log/006", b"6").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(
+        drain(&mut iter),
+        vec![(Bytes::from("log/006"), Bytes::from("6"))]
+    );
+
+    // only the latest snapshot is pinned
+    assert_eq!(storage.inner.mvcc().ts.lock().1.num_retained_snapshots(), 1);
+    drop(iter);
+    assert_eq!(storage.inner.mvcc().ts.lock().1.num_retained_snapshots(), 0);
+}
+
+#[test]
+fn test_tailing_iterator_ingested_files() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(dir.path().join("db"), options).unwrap();
+    storage.put(b"log/001", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let mut iter = storage
+        .tailing_scan(Bound::Included(b"log/"), Bound::Excluded(b"log0"))
+        .unwrap();
+    assert_eq!(
+        drain(&mut iter),
+        vec![(Bytes::from("log/001"), Bytes::from("1"))]
+    );
+
+    // an ingested file is not in any memtable the iterator has read
+    let path = dir.path().join("external.sst");
+    let mut writer = SstFileWriter::create(&path, 4096);
+    writer.put(b"log/002", b"2").unwrap();
+    writer.put(b"log/003", b"3").unwrap();
+    writer.finish().unwrap();
+    storage.ingest(&[path]).unwrap();
+    storage.put(b"log/004", b"4").unwrap();
+    assert_eq!(
+        drain(&mut iter),
+        vec![
+            (Bytes::from("log/002"), Bytes::from("2")),
+            (Bytes::from("log/003"), Bytes::from("3")),
+            (Bytes::from("log/004"), Bytes::from("4")),
+        ]
+    );
+
+    // a seek reads the SSTs skipped by the last poll
+    iter.seek(b"log/001").unwrap();
+    assert_eq!(drain(&mut iter).len(), 4);
+}
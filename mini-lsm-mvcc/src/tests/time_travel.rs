@@ -0,0 +1,106 @@
+use std::{ops::Bound, time::Duration};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{HistoryRetention, LsmStorageOptions, MiniLsm},
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+fn put_versions(storage: &MiniLsm) {
+    // key `a` is overwritten with 1..=10 (except 6), key `b` is written at ts 2 and deleted at ts 7
+    for i in 1..=10 {
+        if i == 6 {
+            storage.delete(b"b").unwrap();
+            continue;
+        }
+        storage.put(b"a", format!("{}", i).as_bytes()).unwrap();
+        if i == 1 {
+            storage.put(b"b", b"1").unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_time_travel_commits_retention() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.history_retention = Some(HistoryRetention::Commits(4));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    put_versions(&storage);
+    let latest = storage.inner.mvcc().latest_commit_ts();
+    assert_eq!(latest, 11);
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    assert_eq!(storage.inner.mvcc().min_readable_ts(), 7);
+    assert_eq!(
+        storage.get_as_of(b"a", 11).unwrap(),
+        Some(Bytes::from("10"))
+    );
+    assert_eq!(storage.get_as_of(b"a", 8).unwrap(), Some(Bytes::from("7")));
+    assert_eq!(storage.get_as_of(b"a", 7).unwrap(), Some(Bytes::from("5")));
+    assert_eq!(storage.get_as_of(b"b", 7).unwrap(), None);
+    assert!(storage.get_as_of(b"a", 6).is_err());
+    // reading beyond the latest commit is the same as reading the latest commit
+    assert_eq!(
+        storage.get_as_of(b"a", 100).unwrap(),
+        Some(Bytes::from("10"))
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_as_of(Bound::Unbounded, Bound::Unbounded, 9)
+            .unwrap(),
+        vec![(Bytes::from("a"), Bytes::from("8"))],
+    );
+}
+
+#[test]
+fn test_time_travel_duration_retention() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.history_retention = Some(HistoryRetention::Duration(Duration::from_secs(3600)));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    put_versions(&storage);
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    assert_eq!(storage.inner.mvcc().min_readable_ts(), 0);
+    assert_eq!(storage.get_as_of(b"a", 0).unwrap(), None);
+    assert_eq!(storage.get_as_of(b"a", 1).unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get_as_of(b"b", 2).unwrap(), Some(Bytes::from("1")));
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_as_of(Bound::Unbounded, Bound::Unbounded, 5)
+            .unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("4")),
+            (Bytes::from("b"), Bytes::from("1")),
+        ],
+    );
+}
+
+#[test]
+fn test_time_travel_without_retention() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    put_versions(&storage);
+    // history is readable until compaction garbage collects it
+    assert_eq!(storage.get_as_of(b"a", 3).unwrap(), Some(Bytes::from("2")));
+    let snapshot = storage.snapshot_as_of(3).unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(Bytes::from("2")));
+    assert!(storage.get_as_of(b"a", 2).is_err());
+    assert_eq!(storage.get_as_of(b"a", 3).unwrap(), Some(Bytes::from("2")));
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert!(storage.get_as_of(b"a", 3).is_err());
+    assert_eq!(
+        storage.get_as_of(b"a", 11).unwrap(),
+        Some(Bytes::from("10"))
+    );
+}
@@ -0,0 +1,140 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+fn num_spill_files(dir: &tempfile::TempDir) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "spill")
+        })
+        .count()
+}
+
+#[test]
+fn test_txn_spill() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.txn_spill_threshold = 64;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"key00", b"old").unwrap();
+    storage.put(b"key99", b"old").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    for i in 0..40 {
+        txn.put(format!("key{:02}", i).as_bytes(), b"v1");
+    }
+    // newer writes shadow spilled ones
+    txn.put(b"key05", b"v2");
+    txn.delete(b"key00");
+    txn.delete(b"key99");
+    assert!(num_spill_files(&dir) > 1);
+    assert_eq!(txn.get(b"key00").unwrap(), None);
+    assert_eq!(txn.get(b"key05").unwrap(), Some(Bytes::from("v2")));
+    assert_eq!(txn.get(b"key06").unwrap(), Some(Bytes::from("v1")));
+    assert_eq!(
+        txn.multi_get(&[&b"key99"[..], b"key39", b"key40"]).unwrap(),
+        vec![None, Some(Bytes::from("v1")), None]
+    );
+    check_lsm_iter_result_by_key(
+        &mut txn
+            .scan(Bound::Excluded(b"key03"), Bound::Included(b"key06"))
+            .unwrap(),
+        vec![
+            (Bytes::from("key04"), Bytes::from("v1")),
+            (Bytes::from("key05"), Bytes::from("v2")),
+            (Bytes::from("key06"), Bytes::from("v1")),
+        ],
+    );
+    txn.commit().unwrap();
+    drop(txn);
+    assert_eq!(num_spill_files(&dir), 0);
+
+    assert_eq!(storage.get(b"key00").unwrap(), None);
+    assert_eq!(storage.get(b"key05").unwrap(), Some(Bytes::from("v2")));
+    assert_eq!(storage.get(b"key99").unwrap(), None);
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 39);
+}
+
+#[test]
+fn test_txn_spill_rollback() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.txn_spill_threshold = 64;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let txn = storage.new_txn().unwrap();
+    for i in 0..10 {
+        txn.put(format!("key{:02}", i).as_bytes(), b"v1");
+    }
+    txn.set_savepoint();
+    for i in 0..20 {
+        txn.put(format!("key{:02}", i).as_bytes(), b"v2");
+    }
+    assert_eq!(txn.get(b"key15").unwrap(), Some(Bytes::from("v2")));
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(b"key05").unwrap(), Some(Bytes::from("v1")));
+    assert_eq!(txn.get(b"key15").unwrap(), None);
+    txn.rollback();
+    assert_eq!(txn.get(b"key05").unwrap(), None);
+    assert_eq!(num_spill_files(&dir), 0);
+    txn.put(b"key05", b"v3");
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"key05").unwrap(), Some(Bytes::from("v3")));
+    assert_eq!(storage.get(b"key06").unwrap(), None);
+}
+
+#[test]
+fn test_txn_spill_commit_failure() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.txn_spill_threshold = 32 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"key000", b"old").unwrap();
+    let txn = storage.new_txn().unwrap();
+    for i in 0..400 {
+        txn.put(format!("key{:03}", i).as_bytes(), &[b'x'; 100]);
+    }
+    assert_eq!(num_spill_files(&dir), 1);
+    // the spilled run can no longer be read after its first block
+    let spill = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "spill"))
+        .unwrap();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(spill)
+        .unwrap()
+        .set_len(6000)
+        .unwrap();
+
+    // the records written before the failure do not become visible, and the ts is not reused
+    let initial_ts = storage.inner.mvcc().latest_commit_ts();
+    assert!(txn.commit().is_err());
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), initial_ts + 1);
+    assert_eq!(storage.get(b"key000").unwrap(), Some(Bytes::from("old")));
+    assert_eq!(storage.get(b"key001").unwrap(), None);
+    storage.put(b"key001", b"new").unwrap();
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), initial_ts + 2);
+    assert_eq!(storage.get(b"key000").unwrap(), Some(Bytes::from("old")));
+    assert_eq!(storage.get(b"key001").unwrap(), Some(Bytes::from("new")));
+}
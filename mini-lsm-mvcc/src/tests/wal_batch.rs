@@ -0,0 +1,157 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+};
+
+fn wal_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
+
+/// The last WAL segment, which new entries are appended to.
+fn current_wal_path(dir: &Path) -> PathBuf {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wal"))
+        .max()
+        .unwrap()
+}
+
+#[test]
+fn test_wal_torn_batch_is_dropped() {
+    let dir = tempdir().unwrap();
+    let mut options = wal_options();
+    options.txn_spill_threshold = 64;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let wal_path = current_wal_path(dir.path());
+    storage.sync().unwrap();
+    let synced_len = std::fs::metadata(&wal_path).unwrap().len();
+
+    // a transaction large enough to spill is still a single WAL entry
+    let txn = storage.new_txn().unwrap();
+    for i in 0..20 {
+        txn.put(format!("key{:02}", i).as_bytes(), b"value");
+    }
+    txn.commit().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // cut off the end of the entry, as if the process crashed while writing it
+    let len = std::fs::metadata(&wal_path).unwrap().len();
+    assert!(len > synced_len + 8);
+    OpenOptions::new()
+        .write(true)
+        .open(&wal_path)
+        .unwrap()
+        .set_len(len - 8)
+        .unwrap();
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    for i in 0..20 {
+        assert_eq!(
+            storage.get(format!("key{:02}", i).as_bytes()).unwrap(),
+            None
+        );
+    }
+    // later entries go to a new segment, so that they are recovered after the torn entry
+    assert_ne!(current_wal_path(dir.path()), wal_path);
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(b"b", b"2"),
+            WriteBatchRecord::Del(b"a"),
+        ])
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_wal_partial_header_is_dropped() {
+    let dir = tempdir().unwrap();
+    let options = wal_options();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let wal_path = current_wal_path(dir.path());
+    storage.close().unwrap();
+    drop(storage);
+
+    let mut file = OpenOptions::new().append(true).open(&wal_path).unwrap();
+    file.write_all(&[0, 0]).unwrap();
+    drop(file);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_wal_streamed_txn_is_recovered_all_or_nothing() {
+    let dir = tempdir().unwrap();
+    let mut options = wal_options();
+    options.txn_spill_threshold = 64 << 10;
+    let value = vec![b'x'; 60000];
+    let commit_large_txn = |storage: &MiniLsm, prefix: &str| {
+        let txn = storage.new_txn().unwrap();
+        for i in 0..30 {
+            txn.put(format!("{}{:02}", prefix, i).as_bytes(), &value);
+        }
+        txn.commit().unwrap();
+    };
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    // the spilled transaction is appended in more than one chunk, and replayed as a whole
+    commit_large_txn(&storage, "x");
+    let state = storage.inner.state.read().clone();
+    let max_lsn = std::iter::once(&state.memtable)
+        .chain(&state.imm_memtables)
+        .map(|memtable| memtable.max_lsn())
+        .max();
+    assert_eq!(max_lsn, Some(3));
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.wal_recovery_report().unwrap().replayed_entries, 2);
+    for i in 0..30 {
+        assert_eq!(
+            storage.get(format!("x{:02}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(value.clone()))
+        );
+    }
+
+    // the earlier chunks are not replayed if the commit entry is cut off
+    commit_large_txn(&storage, "y");
+    let wal_path = current_wal_path(dir.path());
+    storage.close().unwrap();
+    drop(storage);
+    let len = std::fs::metadata(&wal_path).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&wal_path)
+        .unwrap()
+        .set_len(len - 8)
+        .unwrap();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..30 {
+        assert_eq!(
+            storage.get(format!("x{:02}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(value.clone()))
+        );
+        assert_eq!(storage.get(format!("y{:02}", i).as_bytes()).unwrap(), None);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{fs::OpenOptions, os::unix::fs::FileExt, path::Path};
+
+use bytes::Bytes;
+use tempfile::{tempdir, TempDir};
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WalRecoveryMode},
+    wal::WalCorruption,
+};
+
+/// The size of an entry putting a 5-byte key and a 5-byte value: an 11-byte fragment header, the LSN, the entry kind
+/// and the record.
+const ENTRY_SIZE: u64 = 11 + 8 + 1 + 22;
+
+fn wal_options(mode: WalRecoveryMode) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal_recovery_mode = mode;
+    options
+}
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key{:02}", i).into_bytes()
+}
+
+/// Write 5 entries to the first WAL segment, and flip a byte in the entry `corrupt`.
+fn write_corrupted_wal(corrupt: u64) -> TempDir {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options(WalRecoveryMode::default())).unwrap();
+    for i in 0..5 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+    flip_byte(&dir.path().join("00001.wal"), corrupt * ENTRY_SIZE + 20);
+    dir
+}
+
+fn flip_byte(path: &Path, offset: u64) {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .unwrap();
+    let mut byte = [0];
+    file.read_exact_at(&mut byte, offset).unwrap();
+    file.write_all_at(&[byte[0] ^ 0xff], offset).unwrap();
+}
+
+fn check_keys(storage: &MiniLsm, present: &[usize], missing: &[usize]) {
+    for i in present {
+        assert_eq!(
+            storage.get(&key_of(*i)).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+    for i in missing {
+        assert_eq!(storage.get(&key_of(*i)).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_wal_recovery_absolute_consistency() {
+    let dir = write_corrupted_wal(4);
+    assert!(MiniLsm::open(&dir, wal_options(WalRecoveryMode::AbsoluteConsistency)).is_err());
+    let storage = MiniLsm::open(
+        &dir,
+        wal_options(WalRecoveryMode::TolerateCorruptedTailRecords),
+    )
+    .unwrap();
+    check_keys(&storage, &[0, 1, 2, 3], &[4]);
+    storage.close().unwrap();
+    drop(storage);
+    // the corrupted tail has been removed
+    let storage = MiniLsm::open(&dir, wal_options(WalRecoveryMode::AbsoluteConsistency)).unwrap();
+    check_keys(&storage, &[0, 1, 2, 3], &[4]);
+}
+
+#[test]
+fn test_wal_recovery_tolerate_corrupted_tail() {
+    let dir = write_corrupted_wal(2);
+    let options = wal_options(WalRecoveryMode::TolerateCorruptedTailRecords);
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+
+    let dir = write_corrupted_wal(4);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_keys(&storage, &[0, 1, 2, 3], &[4]);
+    let report = storage.wal_recovery_report().unwrap();
+    assert_eq!(report.replayed_entries, 4);
+    assert_eq!(
+        report.corruptions,
+        vec![WalCorruption {
+            segment_id: 1,
+            offset: 4 * ENTRY_SIZE,
+            len: ENTRY_SIZE,
+        }]
+    );
+    assert_eq!(report.dropped_entries, 0);
+}
+
+#[test]
+fn test_wal_recovery_point_in_time() {
+    let dir = write_corrupted_wal(2);
+    let options = wal_options(WalRecoveryMode::PointInTime);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check_keys(&storage, &[0, 1], &[2, 3, 4]);
+    let report = storage.wal_recovery_report().unwrap();
+    assert_eq!(report.replayed_entries, 2);
+    assert_eq!(report.dropped_bytes(), ENTRY_SIZE);
+    assert_eq!(report.dropped_entries, 2);
+
+    // new entries continue from the recovered point, and are recovered after the old ones
+    storage.put(&key_of(3), b"value").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, wal_options(WalRecoveryMode::AbsoluteConsistency)).unwrap();
+    check_keys(&storage, &[0, 1, 3], &[2, 4]);
+}
+
+#[test]
+fn test_wal_recovery_point_in_time_across_segments() {
+    let dir = tempdir().unwrap();
+    let mut options = wal_options(WalRecoveryMode::default());
+    // one entry per segment
+    options.wal_segment_size = ENTRY_SIZE as usize;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..4 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+    flip_byte(&dir.path().join("00001.wal"), 20);
+
+    options.wal_recovery_mode = WalRecoveryMode::PointInTime;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check_keys(&storage, &[], &[0, 1, 2, 3]);
+    assert_eq!(storage.wal_recovery_report().unwrap().dropped_entries, 3);
+    // the segments after the corruption are removed rather than reused
+    for id in 2..=4 {
+        assert!(!dir.path().join(format!("{:05}.wal", id)).exists());
+    }
+    storage.put(&key_of(4), b"value").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    for mode in [
+        WalRecoveryMode::TolerateCorruptedTailRecords,
+        WalRecoveryMode::PointInTime,
+    ] {
+        options.wal_recovery_mode = mode;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        check_keys(&storage, &[4], &[0, 1, 2, 3]);
+        storage.close().unwrap();
+    }
+}
+
+#[test]
+fn test_wal_recovery_skip_corrupted_records() {
+    let dir = write_corrupted_wal(2);
+    let options = wal_options(WalRecoveryMode::SkipAnyCorruptedRecords);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check_keys(&storage, &[0, 1, 3, 4], &[2]);
+    let report = storage.wal_recovery_report().unwrap();
+    assert_eq!(report.replayed_entries, 4);
+    assert_eq!(report.dropped_bytes(), ENTRY_SIZE);
+
+    storage.put(&key_of(5), b"value").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_keys(&storage, &[0, 1, 3, 4, 5], &[2]);
+}
+
+#[test]
+fn test_wal_entry_across_blocks() {
+    let dir = tempdir().unwrap();
+    let options = wal_options(WalRecoveryMode::AbsoluteConsistency);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let value = vec![b'v'; 1000];
+    // an entry of about 100 KiB spans 4 blocks
+    let txn = storage.new_txn().unwrap();
+    for i in 0..100 {
+        txn.put(&key_of(i), &value);
+    }
+    txn.commit().unwrap();
+    storage.put(b"last", b"value").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..100 {
+        assert_eq!(storage.get(&key_of(i)).unwrap().unwrap(), value);
+    }
+    assert_eq!(storage.get(b"last").unwrap(), Some(Bytes::from("value")));
+    storage.close().unwrap();
+    drop(storage);
+
+    // a corrupted middle fragment drops the whole entry
+    flip_byte(&dir.path().join("00001.wal"), 40 << 10);
+    let storage =
+        MiniLsm::open(&dir, wal_options(WalRecoveryMode::SkipAnyCorruptedRecords)).unwrap();
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+    assert_eq!(storage.get(b"last").unwrap(), Some(Bytes::from("value")));
+    let report = storage.wal_recovery_report().unwrap();
+    assert_eq!(report.corruptions.len(), 1);
+    assert_eq!(report.corruptions[0].offset, 0);
+    assert!(report.corruptions[0].len > 96 << 10);
+}
+
+#[test]
+fn test_wal_recovery_corrupted_segment_id() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options(WalRecoveryMode::default())).unwrap();
+    for i in 0..5 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+    // the last byte of the segment id in the header of entry 2
+    flip_byte(&dir.path().join("00001.wal"), 2 * ENTRY_SIZE + 10);
+
+    // the entry is corrupted, rather than the end of the segment
+    let options = wal_options(WalRecoveryMode::TolerateCorruptedTailRecords);
+    assert!(MiniLsm::open(&dir, options).is_err());
+    let options = wal_options(WalRecoveryMode::SkipAnyCorruptedRecords);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_keys(&storage, &[0, 1, 3, 4], &[2]);
+    assert_eq!(
+        storage.wal_recovery_report().unwrap().corruptions,
+        vec![WalCorruption {
+            segment_id: 1,
+            offset: 2 * ENTRY_SIZE,
+            len: ENTRY_SIZE,
+        }]
+    );
+}
+
+#[test]
+fn test_wal_recovery_recycled_segment() {
+    let dir = tempdir().unwrap();
+    let mut options = wal_options(WalRecoveryMode::AbsoluteConsistency);
+    // one entry per segment
+    options.wal_segment_size = 64;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..4 {
+        storage.put(&key_of(i), &[b'v'; 64]).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let (_, recycled) = storage.inner.wal.as_ref().unwrap().for_testing_segments();
+    assert!(!recycled.is_empty());
+    // the shorter entries end within a fragment of the old content of the recycled files
+    for i in 4..8 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage
+        .wal_recovery_report()
+        .unwrap()
+        .corruptions
+        .is_empty());
+    check_keys(&storage, &[4, 5, 6, 7], &[]);
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    watch::WatchEvent,
+};
+
+fn event(key: &str, value: Option<&str>, commit_ts: u64) -> WatchEvent {
+    WatchEvent {
+        key: Bytes::copy_from_slice(key.as_bytes()),
+        value: value.map(|value| Bytes::copy_from_slice(value.as_bytes())),
+        commit_ts,
+    }
+}
+
+#[test]
+fn test_watch_prefix() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"user/0", b"before").unwrap();
+    let watcher = storage.watch(b"user/");
+    storage.put(b"user/1", b"a").unwrap();
+    storage.put(b"order/1", b"b").unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"user/2"[..], &b"c"[..]),
+            WriteBatchRecord::Del(&b"user/1"[..]),
+            WriteBatchRecord::Put(&b"order/2"[..], &b"d"[..]),
+        ])
+        .unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"user/3", b"e");
+    txn.commit().unwrap();
+
+    let mut events = Vec::new();
+    while let Some(event) = watcher.try_recv().unwrap() {
+        // the write is visible when the event is delivered
+        assert!(storage.inner.mvcc().latest_commit_ts() >= event.commit_ts);
+        events.push(event);
+    }
+    assert_eq!(
+        events,
+        vec![
+            event("user/1", Some("a"), 2),
+            event("user/2", Some("c"), 4),
+            event("user/1", None, 4),
+            event("user/3", Some("e"), 5),
+        ]
+    );
+    assert!(!watcher.is_lagged());
+    assert_eq!(
+        watcher.recv_timeout(Duration::from_millis(10)).unwrap(),
+        None
+    );
+
+    let handle = std::thread::spawn(move || watcher.recv().unwrap());
+    storage.put(b"user/4", b"f").unwrap();
+    assert_eq!(handle.join().unwrap(), event("user/4", Some("f"), 6));
+}
+
+#[test]
+fn test_watch_lagged() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let watcher = storage.watch_with_capacity(b"", 2);
+    let other = storage.watch_with_capacity(b"", 10);
+    for i in 0..3 {
+        storage.put(format!("{}", i).as_bytes(), b"v").unwrap();
+    }
+    assert!(watcher.is_lagged());
+    assert_eq!(watcher.try_recv().unwrap(), Some(event("0", Some("v"), 1)));
+    assert_eq!(watcher.try_recv().unwrap(), Some(event("1", Some("v"), 2)));
+    assert!(watcher.try_recv().is_err());
+    assert!(watcher.recv().is_err());
+
+    // other watchers are not affected
+    assert!(!other.is_lagged());
+    for i in 0..3 {
+        assert_eq!(other.recv().unwrap().commit_ts, i + 1);
+    }
+    // dropped watchers are unsubscribed
+    drop(other);
+    storage.put(b"3", b"v").unwrap();
+    assert_eq!(storage.inner.watchers.for_testing_num_watchers(), 0);
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord, WriteOptions},
+};
+
+fn wal_len_on_disk(dir: &Path) -> u64 {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wal"))
+        .map(|path| std::fs::metadata(path).unwrap().len())
+        .sum()
+}
+
+#[test]
+fn test_sync_write() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let sync = WriteOptions {
+        sync: true,
+        disable_wal: false,
+    };
+    storage.put(b"a", b"1").unwrap();
+    assert_eq!(wal_len_on_disk(dir.path()), 0);
+    storage.put_with_options(b"b", b"1", sync).unwrap();
+    let len = wal_len_on_disk(dir.path());
+    assert!(len > 0);
+    storage
+        .write_batch_with_options(
+            &[
+                WriteBatchRecord::Put(b"c", b"1"),
+                WriteBatchRecord::Del(b"a"),
+            ],
+            sync,
+        )
+        .unwrap();
+    assert!(wal_len_on_disk(dir.path()) > len);
+    let len = wal_len_on_disk(dir.path());
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"d", b"1");
+    txn.commit_with_options(sync).unwrap();
+    assert!(wal_len_on_disk(dir.path()) > len);
+}
+
+#[test]
+fn test_disable_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let no_wal = WriteOptions {
+        sync: false,
+        disable_wal: true,
+    };
+    storage.put(b"a", b"1").unwrap();
+    storage.put_with_options(b"b", b"1", no_wal).unwrap();
+    storage.delete_with_options(b"a", no_wal).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+    assert!(storage
+        .put_with_options(
+            b"c",
+            b"1",
+            WriteOptions {
+                sync: true,
+                disable_wal: true,
+            },
+        )
+        .is_err());
+    storage.close().unwrap();
+    drop(storage);
+
+    // the memtable is not flushed on close, so writes without WAL are lost
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+}
@@ -1,114 +1,753 @@
+use std::collections::VecDeque;
 use std::fs::{File, OpenOptions};
-use std::hash::Hasher;
 use std::io::{BufWriter, Read, Write};
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
 use bytes::{Buf, BufMut, Bytes};
-use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
 use crate::key::{KeyBytes, KeySlice};
+use crate::lsm_storage::WalRecoveryMode;
 
+/// At most this many obsolete segments are kept for reuse, the others are removed.
+const MAX_RECYCLED_SEGMENTS: usize = 4;
+
+/// Segments are written in blocks of this size. A fragment never crosses a block boundary, so that a corrupted
+/// length only affects the rest of its block.
+const BLOCK_SIZE: usize = 32 << 10;
+
+/// The size of the header of a fragment: checksum (u32), payload length (u16), type (u8) and segment id (u32).
+const FRAGMENT_HEADER_SIZE: usize = 11;
+
+/// An entry fits into one fragment.
+const FRAGMENT_FULL: u8 = 1;
+/// The first fragment of an entry spanning multiple blocks.
+const FRAGMENT_FIRST: u8 = 2;
+const FRAGMENT_MIDDLE: u8 = 3;
+const FRAGMENT_LAST: u8 = 4;
+
+/// A write too large to be encoded at once is appended in entries of about this size.
+pub(crate) const TXN_CHUNK_SIZE: usize = 1 << 20;
+
+/// How an entry is replayed, stored after its LSN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalEntryKind {
+    /// A complete batch, replayed on its own.
+    Batch = 0,
+    /// The first chunk of a write appended in several entries. The chunks are only replayed once the commit entry
+    /// follows them, the entries of a write that failed or was cut off by a crash are dropped.
+    TxnBegin = 1,
+    TxnPart = 2,
+    /// The last chunk of a write appended in several entries.
+    TxnCommit = 3,
+}
+
+/// A write-ahead log shared by all memtables. Each entry is a batch of records tagged with a log sequence number
+/// (LSN), which increases by one with every entry. The log is split into segment files, and segments whose
+/// entries have all been flushed to SSTs are recycled for later segments instead of being removed.
+///
+/// Like in LevelDB, a segment is a sequence of 32 KiB blocks, and an entry is split into fragments that fill up
+/// the blocks. Each fragment carries the id of its segment, so that the old content of a recycled file is never
+/// mistaken for entries of the new segment.
 pub struct Wal {
-    file: Arc<Mutex<BufWriter<File>>>,
+    dir: PathBuf,
+    segment_size: usize,
+    inner: Mutex<WalInner>,
+    recovery_report: WalRecoveryReport,
 }
 
-impl Wal {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(
+struct WalInner {
+    current: Segment,
+    /// Full segments, from oldest to newest, with the largest LSN in each of them.
+    closed: VecDeque<(u32, u64)>,
+    /// Obsolete segments to be reused.
+    recycled: Vec<u32>,
+    next_segment_id: u32,
+    last_lsn: u64,
+    flushed_lsn: u64,
+}
+
+struct Segment {
+    id: u32,
+    file: BufWriter<File>,
+    size: usize,
+    max_lsn: u64,
+}
+
+/// The records of one WAL entry, which is recovered all-or-nothing.
+#[derive(Default)]
+pub struct WalBatch {
+    buf: Vec<u8>,
+}
+
+impl WalBatch {
+    pub fn put(&mut self, key: KeySlice, value: &[u8]) {
+        self.buf.put_u16(key.key_len() as u16);
+        self.buf.put_slice(key.key_ref());
+        self.buf.put_u64(key.ts());
+        self.buf.put_u16(value.len() as u16);
+        self.buf.put_slice(value);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The size of the encoded records.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+/// An entry replayed from the WAL.
+pub struct WalEntry {
+    pub lsn: u64,
+    pub records: Vec<(KeyBytes, Bytes)>,
+}
+
+impl WalEntryKind {
+    fn decode(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Batch),
+            1 => Some(Self::TxnBegin),
+            2 => Some(Self::TxnPart),
+            3 => Some(Self::TxnCommit),
+            _ => None,
+        }
+    }
+}
+
+/// A range of a segment dropped during recovery, because it is corrupted or cut off by a crash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalCorruption {
+    pub segment_id: u32,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// What recovering the WAL replayed and dropped.
+#[derive(Debug, Clone, Default)]
+pub struct WalRecoveryReport {
+    /// The number of entries replayed into the memtables.
+    pub replayed_entries: usize,
+    /// The dropped ranges, in log order.
+    pub corruptions: Vec<WalCorruption>,
+    /// The number of intact entries dropped because they follow a corruption, in point-in-time recovery.
+    pub dropped_entries: usize,
+}
+
+impl WalRecoveryReport {
+    pub fn dropped_bytes(&self) -> u64 {
+        self.corruptions.iter().map(|c| c.len).sum()
+    }
+}
+
+enum SegmentItem {
+    Entry {
+        entry: WalEntry,
+        kind: WalEntryKind,
+        /// The offset of the first fragment of the entry.
+        offset: u64,
+    },
+    Corruption(WalCorruption),
+}
+
+fn segment_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("{:05}.wal", id))
+}
+
+fn fragment_checksum(kind: u8, segment_id: u32, payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[kind]);
+    hasher.update(&segment_id.to_be_bytes());
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+/// Decode the payload of an entry: its LSN and kind followed by the records of the batch.
+fn decode_entry(mut payload: &[u8]) -> Option<(WalEntry, WalEntryKind)> {
+    if payload.remaining() < 9 {
+        return None;
+    }
+    let lsn = payload.get_u64();
+    let kind = WalEntryKind::decode(payload.get_u8())?;
+    let mut records = Vec::new();
+    while payload.has_remaining() {
+        if payload.remaining() < 2 {
+            return None;
+        }
+        let key_len = payload.get_u16() as usize;
+        if payload.remaining() < key_len + 10 {
+            return None;
+        }
+        let key = Bytes::copy_from_slice(&payload[..key_len]);
+        payload.advance(key_len);
+        let ts = payload.get_u64();
+        let value_len = payload.get_u16() as usize;
+        if payload.remaining() < value_len {
+            return None;
+        }
+        let value = Bytes::copy_from_slice(&payload[..value_len]);
+        payload.advance(value_len);
+        records.push((KeyBytes::from_bytes_with_ts(key, ts), value));
+    }
+    Some((WalEntry { lsn, records }, kind))
+}
+
+/// Decode a segment into its entries and the ranges that cannot be decoded. A fragment with a bad checksum is
+/// dropped, a fragment with an impossible length or type drops the rest of its block, and an entry missing some of
+/// its fragments is dropped as a whole. Decoding ends at the end of the file, or at the old content of a recycled
+/// file: zeroes, an intact fragment of another segment, or garbage with no intact fragment of this segment after
+/// it. A fragment with another segment id is a corruption otherwise, e.g., of the id itself.
+fn decode_segment(segment_id: u32, buf: &[u8]) -> Vec<SegmentItem> {
+    let mut items = Vec::new();
+    let corrupted = |items: &mut Vec<SegmentItem>, offset: usize, end: usize| {
+        // adjacent ranges are reported as one
+        if let Some(SegmentItem::Corruption(last)) = items.last_mut() {
+            if last.offset + last.len == offset as u64 {
+                last.len = (end - last.offset as usize) as u64;
+                return;
+            }
+        }
+        items.push(SegmentItem::Corruption(WalCorruption {
+            segment_id,
+            offset: offset as u64,
+            len: (end - offset) as u64,
+        }))
+    };
+    // the offset and payload of the entry being assembled from fragments
+    let mut partial: Option<(usize, Vec<u8>)> = None;
+    let mut pos = 0;
+    while pos < buf.len() {
+        let block_left = BLOCK_SIZE - pos % BLOCK_SIZE;
+        if block_left < FRAGMENT_HEADER_SIZE {
+            // the trailer of a block is padded with zeroes
+            pos += block_left;
+            continue;
+        }
+        if buf.len() - pos < FRAGMENT_HEADER_SIZE {
+            let offset = partial.take().map_or(pos, |(offset, _)| offset);
+            corrupted(&mut items, offset, buf.len());
+            return items;
+        }
+        let mut header = &buf[pos..pos + FRAGMENT_HEADER_SIZE];
+        let checksum = header.get_u32();
+        let len = header.get_u16() as usize;
+        let kind = header.get_u8();
+        let id = header.get_u32();
+        let fragment_end = pos + FRAGMENT_HEADER_SIZE + len;
+        if id != segment_id {
+            let verifies = |id| {
+                FRAGMENT_HEADER_SIZE + len <= block_left
+                    && fragment_end <= buf.len()
+                    && fragment_checksum(kind, id, &buf[pos + FRAGMENT_HEADER_SIZE..fragment_end])
+                        == checksum
+            };
+            // the old content of a recycled file is not aligned with the new fragments within a block, so garbage is
+            // only a corruption if an intact fragment of this segment follows it
+            let old_content = buf[pos..pos + FRAGMENT_HEADER_SIZE].iter().all(|b| *b == 0)
+                || verifies(id)
+                || (!verifies(segment_id) && !has_fragment_after(segment_id, buf, pos));
+            if old_content {
+                if let Some((offset, _)) = partial.take() {
+                    corrupted(&mut items, offset, pos);
+                }
+                return items;
+            }
+        }
+        if FRAGMENT_HEADER_SIZE + len <= block_left && fragment_end > buf.len() {
+            // the fragment is cut off by the end of the file
+            let offset = partial.take().map_or(pos, |(offset, _)| offset);
+            corrupted(&mut items, offset, buf.len());
+            return items;
+        }
+        if FRAGMENT_HEADER_SIZE + len > block_left
+            || !(FRAGMENT_FULL..=FRAGMENT_LAST).contains(&kind)
+        {
+            // the header is corrupted, so the next fragment can only be found at the next block
+            let block_end = (pos + block_left).min(buf.len());
+            let offset = partial.take().map_or(pos, |(offset, _)| offset);
+            corrupted(&mut items, offset, block_end);
+            pos = block_end;
+            continue;
+        }
+        let payload = &buf[pos + FRAGMENT_HEADER_SIZE..fragment_end];
+        if fragment_checksum(kind, id, payload) != checksum {
+            // the checksum of the next fragment tells whether the length was right
+            let offset = partial.take().map_or(pos, |(offset, _)| offset);
+            corrupted(&mut items, offset, fragment_end);
+            pos = fragment_end;
+            continue;
+        }
+        if id != segment_id {
+            // an intact fragment of the old content of a recycled file
+            if let Some((offset, _)) = partial.take() {
+                corrupted(&mut items, offset, pos);
+            }
+            return items;
+        }
+        let complete = match kind {
+            FRAGMENT_FULL | FRAGMENT_FIRST => {
+                if let Some((offset, _)) = partial.take() {
+                    corrupted(&mut items, offset, pos);
+                }
+                if kind == FRAGMENT_FULL {
+                    Some((pos, payload.to_vec()))
+                } else {
+                    partial = Some((pos, payload.to_vec()));
+                    None
+                }
+            }
+            _ => match partial.take() {
+                Some((offset, mut entry)) => {
+                    entry.extend_from_slice(payload);
+                    if kind == FRAGMENT_LAST {
+                        Some((offset, entry))
+                    } else {
+                        partial = Some((offset, entry));
+                        None
+                    }
+                }
+                None => {
+                    // the beginning of the entry is lost
+                    corrupted(&mut items, pos, fragment_end);
+                    None
+                }
+            },
+        };
+        if let Some((offset, payload)) = complete {
+            match decode_entry(&payload) {
+                Some((entry, kind)) => items.push(SegmentItem::Entry {
+                    entry,
+                    kind,
+                    offset: offset as u64,
+                }),
+                None => corrupted(&mut items, offset, fragment_end),
+            }
+        }
+        pos = fragment_end;
+    }
+    if let Some((offset, _)) = partial {
+        corrupted(&mut items, offset, buf.len());
+    }
+    items
+}
+
+/// Replace the chunks of each write appended in several entries with one entry at the LSN of its commit entry. As
+/// the writes are appended one at a time, a write is complete if its chunks have consecutive LSNs up to a commit
+/// entry, and is dropped otherwise.
+fn assemble_txns(entries: Vec<(WalEntry, WalEntryKind)>) -> Vec<WalEntry> {
+    let mut assembled = Vec::with_capacity(entries.len());
+    let mut txn: Option<WalEntry> = None;
+    for (entry, kind) in entries {
+        match kind {
+            WalEntryKind::Batch => {
+                txn = None;
+                assembled.push(entry);
+            }
+            WalEntryKind::TxnBegin => txn = Some(entry),
+            WalEntryKind::TxnPart | WalEntryKind::TxnCommit => match txn.take() {
+                Some(mut pending) if pending.lsn + 1 == entry.lsn => {
+                    pending.lsn = entry.lsn;
+                    pending.records.extend(entry.records);
+                    if kind == WalEntryKind::TxnCommit {
+                        assembled.push(pending);
+                    } else {
+                        txn = Some(pending);
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+    assembled
+}
+
+/// Whether a block after `pos` starts with an intact fragment of the segment.
+fn has_fragment_after(segment_id: u32, buf: &[u8], pos: usize) -> bool {
+    let mut block = (pos / BLOCK_SIZE + 1) * BLOCK_SIZE;
+    while block + FRAGMENT_HEADER_SIZE <= buf.len() {
+        let mut header = &buf[block..block + FRAGMENT_HEADER_SIZE];
+        let checksum = header.get_u32();
+        let len = header.get_u16() as usize;
+        let kind = header.get_u8();
+        let id = header.get_u32();
+        let fragment_end = block + FRAGMENT_HEADER_SIZE + len;
+        if id == segment_id
+            && FRAGMENT_HEADER_SIZE + len <= BLOCK_SIZE
+            && fragment_end <= buf.len()
+            && fragment_checksum(kind, id, &buf[block + FRAGMENT_HEADER_SIZE..fragment_end])
+                == checksum
+        {
+            return true;
+        }
+        block += BLOCK_SIZE;
+    }
+    false
+}
+
+/// Cut off a segment at `len`, so that the dropped content is not recovered again.
+fn truncate_segment(dir: &Path, id: u32, len: u64) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(segment_path(dir, id))
+        .context("failed to truncate WAL segment")?;
+    file.set_len(len)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+impl Segment {
+    fn create(dir: &Path, id: u32, recycled: Option<u32>) -> Result<Self> {
+        let path = segment_path(dir, id);
+        let file = match recycled {
+            Some(old_id) => {
+                // the old content is overwritten from the start, and never decoded as it has another segment id
+                std::fs::rename(segment_path(dir, old_id), &path)?;
                 OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
                     .write(true)
-                    .open(path)
-                    .context("failed to create WAL")?,
-            ))),
+                    .open(&path)
+                    .context("failed to recycle WAL segment")?
+            }
+            None => OpenOptions::new()
+                .read(true)
+                .create_new(true)
+                .write(true)
+                .open(&path)
+                .context("failed to create WAL segment")?,
+        };
+        File::open(dir)?.sync_all()?;
+        Ok(Self {
+            id,
+            file: BufWriter::new(file),
+            size: 0,
+            max_lsn: 0,
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
-        let path = path.as_ref();
-        let mut file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(path)
-            .context("failed to recover from WAL")?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let mut rbuf: &[u8] = buf.as_slice();
-        while rbuf.has_remaining() {
-            let batch_size = rbuf.get_u32() as usize;
-            if rbuf.remaining() < batch_size {
-                bail!("incomplete WAL");
-            }
-            let mut batch_buf = &rbuf[..batch_size];
-            let mut kv_pairs = Vec::new();
-            let mut hasher = crc32fast::Hasher::new();
-            // The checksum computed from the individual components should be the same as a direct checksum on the buffer.
-            // Students' implementation only needs to do a single checksum on the buffer. We compute both for verification purpose.
-            let single_checksum = crc32fast::hash(batch_buf);
-            while batch_buf.has_remaining() {
-                let key_len = batch_buf.get_u16() as usize;
-                hasher.write(&(key_len as u16).to_be_bytes());
-                let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
-                hasher.write(&key);
-                batch_buf.advance(key_len);
-                let ts = batch_buf.get_u64();
-                hasher.write(&ts.to_be_bytes());
-                let value_len = batch_buf.get_u16() as usize;
-                hasher.write(&(value_len as u16).to_be_bytes());
-                let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
-                hasher.write(&value);
-                kv_pairs.push((key, ts, value));
-                batch_buf.advance(value_len);
-            }
-            rbuf.advance(batch_size);
-            let expected_checksum = rbuf.get_u32();
-            let component_checksum = hasher.finalize();
-            assert_eq!(component_checksum, single_checksum);
-            if single_checksum != expected_checksum {
-                bail!("checksum mismatch");
-            }
-            for (key, ts, value) in kv_pairs {
-                skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+    /// Write an entry as fragments filling up the blocks.
+    fn write_entry(&mut self, mut payload: &[u8]) -> Result<()> {
+        let mut first = true;
+        loop {
+            let block_left = BLOCK_SIZE - self.size % BLOCK_SIZE;
+            if block_left < FRAGMENT_HEADER_SIZE {
+                self.file
+                    .write_all(&[0; FRAGMENT_HEADER_SIZE][..block_left])?;
+                self.size += block_left;
+                continue;
+            }
+            let len = payload.len().min(block_left - FRAGMENT_HEADER_SIZE);
+            let last = len == payload.len();
+            let kind = match (first, last) {
+                (true, true) => FRAGMENT_FULL,
+                (true, false) => FRAGMENT_FIRST,
+                (false, false) => FRAGMENT_MIDDLE,
+                (false, true) => FRAGMENT_LAST,
+            };
+            let fragment = &payload[..len];
+            let mut header = Vec::with_capacity(FRAGMENT_HEADER_SIZE);
+            header.put_u32(fragment_checksum(kind, self.id, fragment));
+            header.put_u16(len as u16);
+            header.put_u8(kind);
+            header.put_u32(self.id);
+            self.file.write_all(&header)?;
+            self.file.write_all(fragment)?;
+            self.size += FRAGMENT_HEADER_SIZE + len;
+            payload = &payload[len..];
+            first = false;
+            if last {
+                return Ok(());
             }
         }
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file.get_mut().sync_all()?;
+        Ok(())
+    }
+}
+
+impl Wal {
+    /// Create an empty WAL in `dir`, starting a new segment once a segment grows beyond `segment_size` bytes.
+    pub fn create(dir: impl AsRef<Path>, segment_size: usize) -> Result<Self> {
+        let dir = dir.as_ref();
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
+            dir: dir.to_path_buf(),
+            segment_size,
+            inner: Mutex::new(WalInner {
+                current: Segment::create(dir, 1, None)?,
+                closed: VecDeque::new(),
+                recycled: Vec::new(),
+                next_segment_id: 2,
+                last_lsn: 0,
+                flushed_lsn: 0,
+            }),
+            recovery_report: WalRecoveryReport::default(),
         })
     }
 
-    /// Implement this in week 3, day 5.
-    pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
-        let mut file = self.file.lock();
-        let mut buf = Vec::<u8>::new();
-        for (key, value) in data {
-            buf.put_u16(key.key_len() as u16);
-            buf.put_slice(key.key_ref());
-            buf.put_u64(key.ts());
-            buf.put_u16(value.len() as u16);
-            buf.put_slice(value);
-        }
-        // write batch_size header (u32)
-        file.write_all(&(buf.len() as u32).to_be_bytes())?;
-        // write key-value pairs body
-        file.write_all(&buf)?;
-        // write checksum (u32)
-        file.write_all(&crc32fast::hash(&buf).to_be_bytes())?;
-        Ok(())
+    /// Open the WAL in `dir`, returning the entries after `flushed_lsn` in LSN order. Corrupted and incomplete
+    /// entries are handled according to `mode`, and what was dropped is removed from the log and reported by
+    /// `recovery_report`. New entries are written to a new segment, so that they never follow a dropped entry.
+    pub fn recover(
+        dir: impl AsRef<Path>,
+        segment_size: usize,
+        flushed_lsn: u64,
+        mode: WalRecoveryMode,
+    ) -> Result<(Self, Vec<WalEntry>)> {
+        let dir = dir.as_ref();
+        let mut segment_ids = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "wal") {
+                if let Some(id) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u32>().ok())
+                {
+                    segment_ids.push(id);
+                }
+            }
+        }
+        segment_ids.sort();
+
+        let mut report = WalRecoveryReport::default();
+        // set once point-in-time recovery reaches a corruption, dropping everything after it
+        let mut stopped = false;
+        let mut last_lsn = flushed_lsn;
+        let mut closed = VecDeque::new();
+        let mut obsolete = Vec::new();
+        let mut entries = Vec::new();
+        for id in segment_ids.iter().copied() {
+            let after_cut = stopped;
+            let mut buf = Vec::new();
+            File::open(segment_path(dir, id))
+                .context("failed to recover from WAL")?
+                .read_to_end(&mut buf)?;
+            let items = decode_segment(id, &buf);
+            let last_entry = items
+                .iter()
+                .rposition(|item| matches!(item, SegmentItem::Entry { .. }));
+            let mut truncate_at = None;
+            let mut max_lsn = 0;
+            for (i, item) in items.into_iter().enumerate() {
+                match item {
+                    SegmentItem::Corruption(corruption) => {
+                        if stopped {
+                            continue;
+                        }
+                        let at_tail = last_entry.is_none_or(|last_entry| i > last_entry);
+                        match mode {
+                            WalRecoveryMode::AbsoluteConsistency => {}
+                            WalRecoveryMode::TolerateCorruptedTailRecords if !at_tail => {}
+                            WalRecoveryMode::TolerateCorruptedTailRecords => {
+                                truncate_at.get_or_insert(corruption.offset);
+                                report.corruptions.push(corruption);
+                                continue;
+                            }
+                            WalRecoveryMode::PointInTime => {
+                                stopped = true;
+                                truncate_at = Some(corruption.offset);
+                                report.corruptions.push(corruption);
+                                continue;
+                            }
+                            WalRecoveryMode::SkipAnyCorruptedRecords => {
+                                report.corruptions.push(corruption);
+                                continue;
+                            }
+                        }
+                        bail!(
+                            "corrupted WAL segment {} at offset {} ({} bytes)",
+                            id,
+                            corruption.offset,
+                            corruption.len
+                        );
+                    }
+                    SegmentItem::Entry {
+                        entry,
+                        kind,
+                        offset,
+                    } => {
+                        if stopped {
+                            report.dropped_entries += (entry.lsn > flushed_lsn) as usize;
+                            continue;
+                        }
+                        if entry.lsn <= flushed_lsn {
+                            max_lsn = max_lsn.max(entry.lsn);
+                            continue;
+                        }
+                        if entry.lsn != last_lsn + 1
+                            && mode != WalRecoveryMode::SkipAnyCorruptedRecords
+                        {
+                            if mode != WalRecoveryMode::PointInTime {
+                                bail!(
+                                    "WAL entry {} found in segment {} after entry {}",
+                                    entry.lsn,
+                                    id,
+                                    last_lsn
+                                );
+                            }
+                            // the entries in between are lost, so the log ends here
+                            stopped = true;
+                            truncate_at = Some(offset);
+                            report.dropped_entries += 1;
+                            continue;
+                        }
+                        max_lsn = max_lsn.max(entry.lsn);
+                        last_lsn = last_lsn.max(entry.lsn);
+                        entries.push((entry, kind));
+                    }
+                }
+            }
+            if after_cut {
+                // the log ends at the cut, so the segment must not be recovered or recycled with its entries
+                std::fs::remove_file(segment_path(dir, id))?;
+                continue;
+            }
+            if let Some(len) = truncate_at {
+                truncate_segment(dir, id, len)?;
+            }
+            if max_lsn <= flushed_lsn {
+                obsolete.push(id);
+            } else {
+                closed.push_back((id, max_lsn));
+            }
+        }
+        entries.sort_by_key(|(entry, _)| entry.lsn);
+        let entries = assemble_txns(entries);
+        report.replayed_entries = entries.len();
+
+        let mut recycled = Vec::new();
+        for id in obsolete {
+            if recycled.len() < MAX_RECYCLED_SEGMENTS {
+                recycled.push(id);
+            } else {
+                std::fs::remove_file(segment_path(dir, id))?;
+            }
+        }
+        let next_segment_id = segment_ids.last().map_or(1, |id| id + 1);
+        let current = Segment::create(dir, next_segment_id, recycled.pop())?;
+        let wal = Self {
+            dir: dir.to_path_buf(),
+            segment_size,
+            inner: Mutex::new(WalInner {
+                current,
+                closed,
+                recycled,
+                next_segment_id: next_segment_id + 1,
+                last_lsn,
+                flushed_lsn,
+            }),
+            recovery_report: report,
+        };
+        Ok((wal, entries))
     }
 
-    pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        self.put_batch(&[(key, value)])
+    /// What was dropped when the WAL was recovered.
+    pub fn recovery_report(&self) -> &WalRecoveryReport {
+        &self.recovery_report
+    }
+
+    /// Append a batch as a single entry, returning its LSN.
+    pub fn append(&self, batch: &WalBatch) -> Result<u64> {
+        self.append_entry(batch, WalEntryKind::Batch)
+    }
+
+    /// Append a batch as an entry of the given kind, returning its LSN. The chunks of a write must be appended with
+    /// nothing else in between.
+    pub fn append_entry(&self, batch: &WalBatch, kind: WalEntryKind) -> Result<u64> {
+        let mut inner = self.inner.lock();
+        let lsn = inner.last_lsn + 1;
+        let mut payload = Vec::with_capacity(9 + batch.buf.len());
+        payload.put_u64(lsn);
+        payload.put_u8(kind as u8);
+        payload.put_slice(&batch.buf);
+        inner.current.write_entry(&payload)?;
+        inner.current.max_lsn = lsn;
+        inner.last_lsn = lsn;
+        if inner.current.size >= self.segment_size {
+            self.roll(&mut inner)?;
+        }
+        Ok(lsn)
+    }
+
+    /// Continue in a new segment. The full segment is synced first, so that a sync only needs the current one.
+    fn roll(&self, inner: &mut WalInner) -> Result<()> {
+        inner.current.sync()?;
+        let id = inner.next_segment_id;
+        let segment = Segment::create(&self.dir, id, inner.recycled.pop())?;
+        inner.next_segment_id += 1;
+        let full = std::mem::replace(&mut inner.current, segment);
+        inner.closed.push_back((full.id, full.max_lsn));
+        Ok(())
     }
 
     pub fn sync(&self) -> Result<()> {
-        let mut file = self.file.lock();
-        file.flush()?;
-        file.get_mut().sync_all()?;
+        self.inner.lock().current.sync()
+    }
+
+    /// Mark all entries up to `flushed_lsn` as persisted in SSTs, and recycle the segments only containing such
+    /// entries. The flush must have been recorded in the manifest.
+    pub fn release(&self, flushed_lsn: u64) -> Result<()> {
+        let mut inner = self.inner.lock();
+        inner.flushed_lsn = inner.flushed_lsn.max(flushed_lsn);
+        while let Some((id, max_lsn)) = inner.closed.front().copied() {
+            if max_lsn > inner.flushed_lsn {
+                break;
+            }
+            inner.closed.pop_front();
+            if inner.recycled.len() < MAX_RECYCLED_SEGMENTS {
+                inner.recycled.push(id);
+            } else {
+                std::fs::remove_file(segment_path(&self.dir, id))?;
+            }
+        }
         Ok(())
     }
+
+    /// Copy the segments with entries not persisted in SSTs to `dir`, up to the last appended entry.
+    pub fn copy_to(&self, dir: &Path) -> Result<()> {
+        let mut inner = self.inner.lock();
+        inner.current.file.flush()?;
+        let segments = inner
+            .closed
+            .iter()
+            .map(|(id, _)| (*id, u64::MAX))
+            .chain(std::iter::once((
+                inner.current.id,
+                inner.current.size as u64,
+            )));
+        for (id, len) in segments {
+            // a recycled file may have old content after the entries of the segment
+            let mut src = File::open(segment_path(&self.dir, id))?.take(len);
+            let mut dest = File::create(segment_path(dir, id))?;
+            std::io::copy(&mut src, &mut dest)?;
+            dest.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the segment file `id` is used by the WAL, or created after this call.
+    pub fn is_live_segment(&self, id: u32) -> bool {
+        let inner = self.inner.lock();
+        id == inner.current.id
+            || id >= inner.next_segment_id
+            || inner.closed.iter().any(|(closed_id, _)| *closed_id == id)
+            || inner.recycled.contains(&id)
+    }
+
+    /// Entries up to this LSN are persisted in SSTs.
+    pub fn flushed_lsn(&self) -> u64 {
+        self.inner.lock().flushed_lsn
+    }
+
+    #[cfg(test)]
+    pub(crate) fn for_testing_segments(&self) -> (Vec<u32>, Vec<u32>) {
+        let inner = self.inner.lock();
+        let mut live = inner.closed.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        live.push(inner.current.id);
+        (live, inner.recycled.clone())
+    }
 }
@@ -0,0 +1,146 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TryRecvError, TrySendError};
+use parking_lot::Mutex;
+
+use crate::lsm_storage::WriteBatchRecord;
+
+/// The number of events buffered for a watcher before it is considered lagged.
+pub const DEFAULT_WATCH_CAPACITY: usize = 1024;
+
+/// A committed write to a watched key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    pub key: Bytes,
+    /// `None` if the key was deleted.
+    pub value: Option<Bytes>,
+    pub commit_ts: u64,
+}
+
+struct WatcherSender {
+    prefix: Bytes,
+    sender: Sender<WatchEvent>,
+    lagged: Arc<AtomicBool>,
+}
+
+/// The registry of all watchers of a storage.
+#[derive(Default)]
+pub(crate) struct Watchers {
+    watchers: Mutex<Vec<WatcherSender>>,
+}
+
+impl Watchers {
+    pub(crate) fn watch(&self, prefix: &[u8], capacity: usize) -> Watcher {
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+        let lagged = Arc::new(AtomicBool::new(false));
+        self.watchers.lock().push(WatcherSender {
+            prefix: Bytes::copy_from_slice(prefix),
+            sender,
+            lagged: lagged.clone(),
+        });
+        Watcher { receiver, lagged }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn for_testing_num_watchers(&self) -> usize {
+        self.watchers.lock().len()
+    }
+
+    /// Whether any watcher is interested in the key.
+    pub(crate) fn is_watched(&self, key: &[u8]) -> bool {
+        self.watchers
+            .lock()
+            .iter()
+            .any(|watcher| key.starts_with(&watcher.prefix))
+    }
+
+    /// Deliver the records of a batch committed at `commit_ts`. The caller must hold the write lock so that events
+    /// are delivered in commit order. This never blocks: a watcher whose buffer is full is marked as lagged and
+    /// unsubscribed.
+    pub(crate) fn notify<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>], commit_ts: u64) {
+        let mut watchers = self.watchers.lock();
+        if watchers.is_empty() {
+            return;
+        }
+        watchers.retain(|watcher| {
+            for record in batch {
+                let (key, value) = match record {
+                    WriteBatchRecord::Put(key, value) => (key.as_ref(), Some(value.as_ref())),
+                    WriteBatchRecord::Del(key) => (key.as_ref(), None),
+                };
+                if !key.starts_with(&watcher.prefix) {
+                    continue;
+                }
+                let event = WatchEvent {
+                    key: Bytes::copy_from_slice(key),
+                    value: value.map(Bytes::copy_from_slice),
+                    commit_ts,
+                };
+                match watcher.sender.try_send(event) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        watcher.lagged.store(true, Ordering::SeqCst);
+                        return false;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+            true
+        });
+    }
+}
+
+/// The receiving end of `MiniLsm::watch`. Events are received in commit order. If the watcher falls behind and its
+/// buffer fills up, it is unsubscribed: the buffered events can still be received, after which receiving returns an
+/// error. A lagged consumer can catch up with `MiniLsm::changes_between` starting from the last received commit ts.
+pub struct Watcher {
+    receiver: Receiver<WatchEvent>,
+    lagged: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    /// Whether events have been dropped because the buffer was full.
+    pub fn is_lagged(&self) -> bool {
+        self.lagged.load(Ordering::SeqCst)
+    }
+
+    fn disconnected(&self) -> anyhow::Error {
+        if self.is_lagged() {
+            anyhow!("watcher lagged behind and missed events")
+        } else {
+            anyhow!("storage is closed")
+        }
+    }
+
+    /// Block until the next event is available.
+    pub fn recv(&self) -> Result<WatchEvent> {
+        match self.receiver.recv() {
+            Ok(event) => Ok(event),
+            Err(_) => Err(self.disconnected()),
+        }
+    }
+
+    /// Block until the next event is available or the timeout elapses, returning `None` on timeout.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<WatchEvent>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(self.disconnected()),
+        }
+    }
+
+    /// Receive the next event if one is available.
+    pub fn try_recv(&self) -> Result<Option<WatchEvent>> {
+        match self.receiver.try_recv() {
+            Ok(event) => Ok(Some(event)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(self.disconnected()),
+        }
+    }
+}
@@ -19,6 +19,12 @@ pub trait StorageIterator {
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// Re-position the iterator at the first entry that is >= `key`. Only the iterators of the MVCC engine support
+    /// seeking, and the test harness shares its mock iterator with it.
+    fn seek(&mut self, _key: Self::KeyType<'_>) -> anyhow::Result<()> {
+        anyhow::bail!("seek is not supported")
+    }
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
@@ -57,6 +57,18 @@ impl StorageIterator for MockIterator {
         Ok(())
     }
 
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.index = self
+            .data
+            .partition_point(|(k, _)| KeySlice::for_testing_from_slice_no_ts(k) < key);
+        if let Some(error_when) = self.error_when {
+            if self.index >= error_when {
+                bail!("fake error!");
+            }
+        }
+        Ok(())
+    }
+
     fn key(&self) -> KeySlice {
         if let Some(error_when) = self.error_when {
             if self.index >= error_when {